@@ -1,40 +1,139 @@
-use super::server_actor::{ConnectionStopReason, ServerActor, ServerMessage};
+use super::server_actor::{
+    ConnectionStopReason, EventReplay, ResumedSession, ServerActor, ServerMessage,
+};
 use crate::{
+    config::ServerConfig,
     messages::{
-        inbound::{InboundMessage, InitMessage, MessageBody},
-        outbound::{InitType, OutboundMessage, ReplyData},
+        encoding::Encoding,
+        inbound::{InboundMessage, InitMessage, InitRequest, MessageBody},
+        outbound::{ErrorCode, EventData, InitType, OutboundMessage, ReplyData},
+        profile::Profile,
+        protocol::Protocol,
+        state::ReadingProgress,
     },
-    ResponderDelegate, ResponderTrait,
+    room::{hash_password_blocking, verify_password_blocking, Permission, Poll, Role},
+    ResponderTrait,
 };
 use async_trait::async_trait;
+#[cfg(test)]
 use mockall::mock;
-use nanoid::nanoid;
 use ractor::{
     call, concurrency::JoinHandle, Actor, ActorProcessingErr, ActorRef, Message, MessagingErr,
 };
-use simple_websockets::Responder;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+const INIT_TIMEOUT: Duration = Duration::from_millis(5000);
+/// Longest an invite can stay valid, 30 days.
+const MAX_INVITE_LIFETIME_MS: u64 = 30 * 24 * 60 * 60 * 1000;
 
 #[derive(Debug, Clone)]
 pub struct SessionState {
     pub session_id: String,
-    pub some_random_text: String,
+    pub room_id: String,
+    pub role: Role,
+    /// Latest position the member reported, kept here so it survives a
+    /// reconnect.
+    pub progress: Option<ReadingProgress>,
+    pub profile: Option<Profile>,
+}
+
+#[derive(Debug)]
+pub struct Heartbeat {
+    pub timer_handle: JoinHandle<()>,
+    pub next_ping_id: u64,
+    /// Id and send time of the last ping, until it's answered.
+    pub pending_ping: Option<(u64, Instant)>,
+    pub missed_pings: u32,
+    pub round_trip_time: Option<Duration>,
 }
 
 #[derive(Debug)]
 pub struct ConnectionState {
     pub server_actor: ActorRef<ServerActor>,
+    pub config: ServerConfig,
     pub fsm: FSM,
     pub responder: Box<dyn ResponderTrait>,
     pub session_state: Option<SessionState>,
+    /// Negotiated by the latest init request, even one that failed, so that
+    /// the replies to it are sent in the encoding it asked for.
+    pub protocol: Option<Protocol>,
+    /// Only running for protocols that have heartbeats.
+    pub heartbeat: Option<Heartbeat>,
+    /// Wrong passwords sent over this connection, which bound the attempts of
+    /// clients whose address the server can't count them under.
+    pub failed_password_attempts: u32,
+}
+
+impl ConnectionState {
+    /// JSON until the connection negotiated otherwise.
+    fn encoding(&self) -> Encoding {
+        self.protocol
+            .as_ref()
+            .map_or(Encoding::Json, Protocol::encoding)
+    }
+
+    fn initialize(
+        &mut self,
+        myself: &ActorRef<ConnectionActor>,
+        session_state: SessionState,
+        protocol: Protocol,
+    ) {
+        if protocol.has_heartbeat() {
+            let timer_handle = myself.send_interval(self.config.heartbeat_interval, || {
+                ConnectionMessage::HeartbeatTick
+            });
+
+            self.heartbeat = Some(Heartbeat {
+                timer_handle,
+                next_ping_id: 0,
+                pending_ping: None,
+                missed_pings: 0,
+                round_trip_time: None,
+            });
+        }
+
+        self.session_state = Some(session_state);
+        self.protocol = Some(protocol);
+        self.fsm = FSM::Initialized;
+    }
+
+    fn send_error(&self, id: String, code: ErrorCode, message: String) {
+        OutboundMessage::Error { id, code, message }.send(&*self.responder, self.encoding());
+    }
+
+    fn send_error_code(&self, id: String, code: ErrorCode) {
+        self.send_error(id, code, code.description().into());
+    }
+
+    /// Replies with a `permission_denied` error if the session's role lacks the
+    /// permission. Returns whether the request may proceed.
+    fn require_permission(&self, id: &str, permission: Permission) -> bool {
+        let role = self.session_state.as_ref().unwrap().role;
+
+        if role.has_permission(permission) {
+            return true;
+        }
+
+        self.send_error(
+            id.into(),
+            ErrorCode::PermissionDenied,
+            format!("the {} role lacks the {} permission", role, permission),
+        );
+
+        false
+    }
 }
 
 #[derive(Debug)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum FSM {
     WaitingForInitialization {
         timer_handle: JoinHandle<Result<(), MessagingErr>>,
     },
     Initialized,
+    /// The session was ended with a `leave` request, and anything the client
+    /// sent after it is dropped while the connection closes.
+    Left,
 }
 
 #[derive(Debug)]
@@ -42,7 +141,11 @@ pub enum ConnectionMessage {
     Stop { reason: ConnectionStopReason },
     InitTimeout,
     MalformedInboundMessageReceived,
+    InvalidInboundMessageReceived { id: String, error: String },
     InboundMessageReceived { message: InboundMessage },
+    PushEvent { seq: u64, event: EventData },
+    HeartbeatTick,
+    RoleChanged { role: Role },
 }
 
 impl Message for ConnectionMessage {}
@@ -54,22 +157,28 @@ pub struct ConnectionActor;
 impl Actor for ConnectionActor {
     type Msg = ConnectionMessage;
     type State = ConnectionState;
-    type Arguments = (ActorRef<ServerActor>, Box<dyn ResponderTrait>);
+    type Arguments = (ActorRef<ServerActor>, Box<dyn ResponderTrait>, ServerConfig);
 
     async fn pre_start(
         &self,
         myself: ActorRef<Self>,
-        (server_actor, responder): (ActorRef<ServerActor>, Box<dyn ResponderTrait>),
+        (server_actor, responder, config): (
+            ActorRef<ServerActor>,
+            Box<dyn ResponderTrait>,
+            ServerConfig,
+        ),
     ) -> Result<Self::State, ActorProcessingErr> {
-        let timer_handle = myself.send_after(Duration::from_millis(5000), || {
-            ConnectionMessage::InitTimeout
-        });
+        let timer_handle = myself.send_after(INIT_TIMEOUT, || ConnectionMessage::InitTimeout);
 
         Ok(ConnectionState {
             server_actor,
+            config,
             fsm: FSM::WaitingForInitialization { timer_handle },
             responder,
             session_state: None,
+            protocol: None,
+            heartbeat: None,
+            failed_password_attempts: 0,
         })
     }
 
@@ -80,6 +189,51 @@ impl Actor for ConnectionActor {
         state: &mut Self::State,
     ) -> Result<(), ActorProcessingErr> {
         match (&state.fsm, message) {
+            // WaitingForInitialization; InboundMessageReceived (Init) (unsupported protocol version)
+            (
+                FSM::WaitingForInitialization { timer_handle },
+                ConnectionMessage::InboundMessageReceived {
+                    message:
+                        InboundMessage {
+                            id: _,
+                            body: MessageBody::Init(init_request),
+                        },
+                },
+            ) if init_request.handshake.negotiate().is_none() => {
+                timer_handle.abort();
+
+                myself.send_message(ConnectionMessage::Stop {
+                    reason: ConnectionStopReason::UnsupportedProtocolVersion,
+                })?;
+            }
+
+            // WaitingForInitialization; InboundMessageReceived (Init) (invalid profile)
+            (
+                FSM::WaitingForInitialization { timer_handle: _ },
+                ConnectionMessage::InboundMessageReceived {
+                    message:
+                        InboundMessage {
+                            id,
+                            body: MessageBody::Init(init_request),
+                        },
+                },
+            ) if init_request
+                .init_type
+                .profile()
+                .is_some_and(|profile| profile.validate().is_err()) =>
+            {
+                let error = init_request
+                    .init_type
+                    .profile()
+                    .unwrap()
+                    .validate()
+                    .unwrap_err();
+
+                // the connection is left waiting so that the client can retry
+                state.protocol = init_request.handshake.negotiate();
+                state.send_error(id, ErrorCode::InvalidProfile, error.to_string());
+            }
+
             // WaitingForInitialization; InboundMessageReceived (Init) (Host)
             (
                 FSM::WaitingForInitialization { timer_handle },
@@ -87,30 +241,64 @@ impl Actor for ConnectionActor {
                     message:
                         InboundMessage {
                             id,
-                            body: MessageBody::Init(InitMessage::Host),
+                            body:
+                                MessageBody::Init(InitRequest {
+                                    handshake,
+                                    init_type:
+                                        InitMessage::Host {
+                                            max_members,
+                                            password,
+                                            profile,
+                                        },
+                                }),
                         },
                 },
             ) => {
                 timer_handle.abort();
 
-                let session_id = nanoid!();
-                let room_id = nanoid!();
+                let protocol = handshake
+                    .negotiate()
+                    .expect("should have rejected unsupported protocol versions");
+                state.protocol = Some(protocol.clone());
+
+                // hashing is slow on purpose, so it's kept off the async workers
+                let password_hash = match password {
+                    Some(password) => Some(hash_password_blocking(password).await?),
+                    None => None,
+                };
+                let connection_actor = myself.clone();
+                let client_address = state.responder.client_address();
+                let created_room = call!(state.server_actor, move |reply_port| {
+                    ServerMessage::CreateRoom {
+                        max_members,
+                        password_hash,
+                        connection_actor,
+                        client_address,
+                        profile,
+                        reply_port,
+                    }
+                })?;
 
-                state.session_state = Some(SessionState {
-                    session_id: session_id.clone(),
-                    some_random_text: "None".into(),
-                });
+                let session_id = created_room.session_state.session_id.clone();
+                let room_id = created_room.session_state.room_id.clone();
+                let spectator_token = created_room.spectator_token;
+                let max_members = created_room.max_members;
 
-                state.fsm = FSM::Initialized;
+                state.initialize(&myself, created_room.session_state, protocol.clone());
 
                 OutboundMessage::Reply {
                     id,
-                    data: ReplyData::Init(InitType::Host {
-                        session_id,
-                        room_id,
-                    }),
+                    data: ReplyData::Init {
+                        init_type: InitType::Host {
+                            session_id,
+                            room_id,
+                            spectator_token,
+                            max_members,
+                        },
+                        protocol,
+                    },
                 }
-                .send(&state.responder);
+                .send(&*state.responder, state.encoding());
             }
 
             // WaitingForInitialization; InboundMessageReceived (Init) (Client)
@@ -120,26 +308,258 @@ impl Actor for ConnectionActor {
                     message:
                         InboundMessage {
                             id,
-                            body: MessageBody::Init(InitMessage::Client { room_id: _ }),
+                            body:
+                                MessageBody::Init(InitRequest {
+                                    handshake,
+                                    init_type:
+                                        InitMessage::Client {
+                                            room_id,
+                                            password,
+                                            profile,
+                                        },
+                                }),
                         },
                 },
             ) => {
                 timer_handle.abort();
 
-                let session_id = nanoid!();
+                let protocol = handshake
+                    .negotiate()
+                    .expect("should have rejected unsupported protocol versions");
+                state.protocol = Some(protocol.clone());
 
-                state.session_state = Some(SessionState {
-                    session_id: session_id.clone(),
-                    some_random_text: "None".into(),
-                });
+                let client_address = state.responder.client_address();
+                // the room id the client sent might differ in case or whitespace,
+                // so the canonical one is used to count its failed attempts
+                let (room_id, password_hash) = match call!(state.server_actor, move |reply_port| {
+                    ServerMessage::GetPasswordHash {
+                        room_id,
+                        client_address,
+                        reply_port,
+                    }
+                })? {
+                    Ok(room_password) => room_password,
+                    Err(error) => {
+                        myself.send_message(ConnectionMessage::Stop {
+                            reason: error.into(),
+                        })?;
+                        return Ok(());
+                    }
+                };
 
-                state.fsm = FSM::Initialized;
+                // verified here rather than on the server actor, which every room
+                // would otherwise have to wait on
+                let accepts_password = match (password_hash.clone(), password) {
+                    (None, _) => true,
+                    (Some(password_hash), Some(password)) => {
+                        verify_password_blocking(password_hash, password).await?
+                    }
+                    (Some(_), None) => false,
+                };
 
-                OutboundMessage::Reply {
-                    id,
-                    data: ReplyData::Init(InitType::Client { session_id }),
+                if !accepts_password {
+                    // counted by the server actor per address and per room, so that
+                    // reconnecting doesn't buy more attempts, and per connection for
+                    // clients whose address isn't known
+                    let failed_room_id = room_id.clone();
+                    let server_has_attempts_left = call!(state.server_actor, move |reply_port| {
+                        ServerMessage::ReportFailedPassword {
+                            room_id: failed_room_id,
+                            client_address,
+                            reply_port,
+                        }
+                    })?;
+                    state.failed_password_attempts += 1;
+
+                    if server_has_attempts_left
+                        && state.failed_password_attempts < state.config.max_password_attempts
+                    {
+                        state.fsm = FSM::WaitingForInitialization {
+                            timer_handle: myself
+                                .send_after(INIT_TIMEOUT, || ConnectionMessage::InitTimeout),
+                        };
+
+                        state.send_error_code(id, ErrorCode::InvalidPassword);
+                    } else {
+                        myself.send_message(ConnectionMessage::Stop {
+                            reason: ConnectionStopReason::TooManyPasswordAttempts,
+                        })?;
+                    }
+
+                    return Ok(());
                 }
-                .send(&state.responder);
+
+                let connection_actor = myself.clone();
+                let join_result = call!(state.server_actor, move |reply_port| {
+                    ServerMessage::JoinRoom {
+                        room_id,
+                        spectator_token: None,
+                        password_hash,
+                        connection_actor,
+                        client_address,
+                        profile,
+                        reply_port,
+                    }
+                })?;
+
+                match join_result {
+                    Ok((session_state, host_session_id)) => {
+                        let session_id = session_state.session_id.clone();
+
+                        state.initialize(&myself, session_state, protocol.clone());
+
+                        OutboundMessage::Reply {
+                            id,
+                            data: ReplyData::Init {
+                                init_type: InitType::Client {
+                                    session_id,
+                                    host_session_id,
+                                },
+                                protocol,
+                            },
+                        }
+                        .send(&*state.responder, state.encoding());
+                    }
+                    Err(error) => {
+                        myself.send_message(ConnectionMessage::Stop {
+                            reason: error.into(),
+                        })?;
+                    }
+                };
+            }
+
+            // WaitingForInitialization; InboundMessageReceived (Init) (Spectator)
+            (
+                FSM::WaitingForInitialization { timer_handle },
+                ConnectionMessage::InboundMessageReceived {
+                    message:
+                        InboundMessage {
+                            id,
+                            body:
+                                MessageBody::Init(InitRequest {
+                                    handshake,
+                                    init_type:
+                                        InitMessage::Spectator {
+                                            room_id,
+                                            token,
+                                            profile,
+                                        },
+                                }),
+                        },
+                },
+            ) => {
+                timer_handle.abort();
+
+                let protocol = handshake
+                    .negotiate()
+                    .expect("should have rejected unsupported protocol versions");
+                state.protocol = Some(protocol.clone());
+
+                let connection_actor = myself.clone();
+                let client_address = state.responder.client_address();
+                let join_result = call!(state.server_actor, move |reply_port| {
+                    ServerMessage::JoinRoom {
+                        room_id,
+                        spectator_token: Some(token),
+                        password_hash: None,
+                        connection_actor,
+                        client_address,
+                        profile,
+                        reply_port,
+                    }
+                })?;
+
+                match join_result {
+                    Ok((session_state, host_session_id)) => {
+                        let session_id = session_state.session_id.clone();
+
+                        state.initialize(&myself, session_state, protocol.clone());
+
+                        OutboundMessage::Reply {
+                            id,
+                            data: ReplyData::Init {
+                                init_type: InitType::Spectator {
+                                    session_id,
+                                    host_session_id,
+                                },
+                                protocol,
+                            },
+                        }
+                        .send(&*state.responder, state.encoding());
+                    }
+                    Err(error) => {
+                        myself.send_message(ConnectionMessage::Stop {
+                            reason: error.into(),
+                        })?;
+                    }
+                };
+            }
+
+            // WaitingForInitialization; InboundMessageReceived (Init) (Invite)
+            (
+                FSM::WaitingForInitialization { timer_handle },
+                ConnectionMessage::InboundMessageReceived {
+                    message:
+                        InboundMessage {
+                            id,
+                            body:
+                                MessageBody::Init(InitRequest {
+                                    handshake,
+                                    init_type: InitMessage::Invite { token, profile },
+                                }),
+                        },
+                },
+            ) => {
+                timer_handle.abort();
+
+                let protocol = handshake
+                    .negotiate()
+                    .expect("should have rejected unsupported protocol versions");
+                state.protocol = Some(protocol.clone());
+
+                let connection_actor = myself.clone();
+                let client_address = state.responder.client_address();
+                let join_result = call!(state.server_actor, move |reply_port| {
+                    ServerMessage::RedeemInvite {
+                        token,
+                        connection_actor,
+                        client_address,
+                        profile,
+                        reply_port,
+                    }
+                })?;
+
+                match join_result {
+                    Ok((session_state, host_session_id)) => {
+                        let session_id = session_state.session_id.clone();
+                        let init_type = match session_state.role {
+                            Role::Spectator => InitType::Spectator {
+                                session_id,
+                                host_session_id,
+                            },
+                            _ => InitType::Client {
+                                session_id,
+                                host_session_id,
+                            },
+                        };
+
+                        state.initialize(&myself, session_state, protocol.clone());
+
+                        OutboundMessage::Reply {
+                            id,
+                            data: ReplyData::Init {
+                                init_type,
+                                protocol,
+                            },
+                        }
+                        .send(&*state.responder, state.encoding());
+                    }
+                    Err(error) => {
+                        myself.send_message(ConnectionMessage::Stop {
+                            reason: error.into(),
+                        })?;
+                    }
+                };
             }
 
             // WaitingForInitialization; InboundMessageReceived (Init) (Reconnect)
@@ -149,32 +569,64 @@ impl Actor for ConnectionActor {
                     message:
                         InboundMessage {
                             id,
-                            body: MessageBody::Init(InitMessage::Reconnect { session_id }),
+                            body:
+                                MessageBody::Init(InitRequest {
+                                    handshake,
+                                    init_type:
+                                        InitMessage::Reconnect {
+                                            session_id,
+                                            last_seen_seq,
+                                        },
+                                }),
                         },
                 },
             ) => {
                 timer_handle.abort();
 
-                let dangling_session_option = call!(state.server_actor, move |reply_port| {
-                    ServerMessage::GetDanglingSession {
+                let protocol = handshake
+                    .negotiate()
+                    .expect("should have rejected unsupported protocol versions");
+                state.protocol = Some(protocol.clone());
+
+                let connection_actor = myself.clone();
+                let resumed_session_option = call!(state.server_actor, move |reply_port| {
+                    ServerMessage::ResumeSession {
                         session_id,
+                        last_seen_seq,
+                        connection_actor,
                         reply_port,
                     }
                 })?;
 
-                match dangling_session_option {
-                    Some(dangling_session) => {
-                        dangling_session.timer_handle.abort();
-                        println!("stoped dangling session timer for");
-
-                        state.session_state = Some(dangling_session.session_state);
-                        state.fsm = FSM::Initialized;
+                match resumed_session_option {
+                    Some(ResumedSession {
+                        session_state,
+                        replay,
+                    }) => {
+                        state.initialize(&myself, session_state, protocol.clone());
 
                         OutboundMessage::Reply {
                             id,
-                            data: ReplyData::Init(InitType::Reconnect),
+                            data: ReplyData::Init {
+                                init_type: InitType::Reconnect {
+                                    resync_required: matches!(replay, EventReplay::Resync),
+                                },
+                                protocol,
+                            },
+                        }
+                        .send(&*state.responder, state.encoding());
+
+                        // events broadcast since the reconnect are already queued up
+                        // behind this message, so they'll follow the replayed ones
+                        if let EventReplay::Events(entries) = replay {
+                            for entry in entries {
+                                OutboundMessage::Event {
+                                    seq: entry.index,
+                                    data: entry.item,
+                                }
+                                .send(&*state.responder, state.encoding());
+                            }
                         }
-                        .send(&state.responder);
                     }
                     None => {
                         myself.send_message(ConnectionMessage::Stop {
@@ -202,18 +654,22 @@ impl Actor for ConnectionActor {
                         },
                 },
             ) => {
-                let session_state = state.session_state.as_ref().unwrap();
+                let room_id = state.session_state.as_ref().unwrap().room_id.clone();
+                let string = call!(state.server_actor, move |reply_port| {
+                    ServerMessage::GetRoomStateString {
+                        room_id,
+                        reply_port,
+                    }
+                })?;
 
                 OutboundMessage::Reply {
                     id,
-                    data: ReplyData::GetStateString {
-                        string: session_state.some_random_text.clone(),
-                    },
+                    data: ReplyData::GetStateString { string },
                 }
-                .send(&state.responder);
+                .send(&*state.responder, state.encoding());
             }
 
-            // Initialized; InboundMessageReceived ()
+            // Initialized; InboundMessageReceived (SetStateString)
             (
                 FSM::Initialized,
                 ConnectionMessage::InboundMessageReceived {
@@ -224,15 +680,885 @@ impl Actor for ConnectionActor {
                         },
                 },
             ) => {
-                let session_state = state.session_state.as_mut().unwrap();
+                if !state.require_permission(&id, Permission::WriteState) {
+                    return Ok(());
+                }
 
-                session_state.some_random_text = string;
+                let room_id = state.session_state.as_ref().unwrap().room_id.clone();
+
+                state
+                    .server_actor
+                    .send_message(ServerMessage::SetRoomStateString { room_id, string })?;
 
                 OutboundMessage::Reply {
                     id,
                     data: ReplyData::SetStateString,
                 }
-                .send(&state.responder);
+                .send(&*state.responder, state.encoding());
+            }
+
+            // Initialized; InboundMessageReceived (GetState)
+            (
+                FSM::Initialized,
+                ConnectionMessage::InboundMessageReceived {
+                    message:
+                        InboundMessage {
+                            id,
+                            body: MessageBody::GetState,
+                        },
+                },
+            ) => {
+                let room_id = state.session_state.as_ref().unwrap().room_id.clone();
+                let room_state = call!(state.server_actor, move |reply_port| {
+                    ServerMessage::GetRoomState {
+                        room_id,
+                        reply_port,
+                    }
+                })?;
+
+                OutboundMessage::Reply {
+                    id,
+                    data: ReplyData::GetState { state: room_state },
+                }
+                .send(&*state.responder, state.encoding());
+            }
+
+            // Initialized; InboundMessageReceived (SetState)
+            (
+                FSM::Initialized,
+                ConnectionMessage::InboundMessageReceived {
+                    message:
+                        InboundMessage {
+                            id,
+                            body: MessageBody::SetState { state: room_state },
+                        },
+                },
+            ) => {
+                if !state.require_permission(&id, Permission::WriteState) {
+                    return Ok(());
+                }
+
+                if let Err(error) = room_state.validate() {
+                    state.send_error(id, ErrorCode::InvalidState, error.to_string());
+                    return Ok(());
+                }
+
+                let room_id = state.session_state.as_ref().unwrap().room_id.clone();
+
+                state
+                    .server_actor
+                    .send_message(ServerMessage::SetRoomState {
+                        room_id,
+                        state: room_state,
+                    })?;
+
+                OutboundMessage::Reply {
+                    id,
+                    data: ReplyData::SetState,
+                }
+                .send(&*state.responder, state.encoding());
+            }
+
+            // Initialized; InboundMessageReceived (GetBacklog)
+            (
+                FSM::Initialized,
+                ConnectionMessage::InboundMessageReceived {
+                    message:
+                        InboundMessage {
+                            id,
+                            body: MessageBody::GetBacklog { before, limit },
+                        },
+                },
+            ) => {
+                let room_id = state.session_state.as_ref().unwrap().room_id.clone();
+                let (entries, has_more) = call!(state.server_actor, move |reply_port| {
+                    ServerMessage::GetBacklog {
+                        room_id,
+                        before,
+                        limit,
+                        reply_port,
+                    }
+                })?;
+
+                OutboundMessage::Reply {
+                    id,
+                    data: ReplyData::GetBacklog { entries, has_more },
+                }
+                .send(&*state.responder, state.encoding());
+            }
+
+            // Initialized; InboundMessageReceived (SendChat)
+            (
+                FSM::Initialized,
+                ConnectionMessage::InboundMessageReceived {
+                    message:
+                        InboundMessage {
+                            id,
+                            body: MessageBody::SendChat { text },
+                        },
+                },
+            ) => {
+                if !state.require_permission(&id, Permission::Chat) {
+                    return Ok(());
+                }
+
+                let max_length = state.config.max_chat_message_length;
+                let length = text.chars().count();
+
+                if text.trim().is_empty() || length > max_length {
+                    state.send_error(
+                        id,
+                        ErrorCode::InvalidChatMessage,
+                        format!(
+                            "chat messages must be between 1 and {} characters long",
+                            max_length
+                        ),
+                    );
+                    return Ok(());
+                }
+
+                let session_state = state.session_state.as_ref().unwrap();
+                let room_id = session_state.room_id.clone();
+                let session_id = session_state.session_id.clone();
+                let chat_result = call!(state.server_actor, move |reply_port| {
+                    ServerMessage::SendChat {
+                        room_id,
+                        session_id,
+                        text,
+                        reply_port,
+                    }
+                })?;
+
+                match chat_result {
+                    Ok(()) => OutboundMessage::Reply {
+                        id,
+                        data: ReplyData::SendChat,
+                    }
+                    .send(&*state.responder, state.encoding()),
+                    Err(code) => state.send_error_code(id, code),
+                };
+            }
+
+            // Initialized; InboundMessageReceived (GetChatHistory)
+            (
+                FSM::Initialized,
+                ConnectionMessage::InboundMessageReceived {
+                    message:
+                        InboundMessage {
+                            id,
+                            body: MessageBody::GetChatHistory { before, limit },
+                        },
+                },
+            ) => {
+                let room_id = state.session_state.as_ref().unwrap().room_id.clone();
+                let (entries, has_more) = call!(state.server_actor, move |reply_port| {
+                    ServerMessage::GetChatHistory {
+                        room_id,
+                        before,
+                        limit,
+                        reply_port,
+                    }
+                })?;
+
+                OutboundMessage::Reply {
+                    id,
+                    data: ReplyData::GetChatHistory { entries, has_more },
+                }
+                .send(&*state.responder, state.encoding());
+            }
+
+            // Initialized; InboundMessageReceived (OpenPoll)
+            (
+                FSM::Initialized,
+                ConnectionMessage::InboundMessageReceived {
+                    message:
+                        InboundMessage {
+                            id,
+                            body:
+                                MessageBody::OpenPoll {
+                                    options,
+                                    duration_ms,
+                                },
+                        },
+                },
+            ) => {
+                if !state.require_permission(&id, Permission::ManagePoll) {
+                    return Ok(());
+                }
+
+                if let Err(error) = Poll::validate_options(&options)
+                    .and_then(|()| Poll::validate_duration(duration_ms))
+                {
+                    state.send_error(id, ErrorCode::InvalidPoll, error);
+                    return Ok(());
+                }
+
+                let room_id = state.session_state.as_ref().unwrap().room_id.clone();
+                let open_result = call!(state.server_actor, move |reply_port| {
+                    ServerMessage::OpenPoll {
+                        room_id,
+                        options,
+                        duration: duration_ms.map(Duration::from_millis),
+                        reply_port,
+                    }
+                })?;
+
+                match open_result {
+                    Ok(poll_id) => OutboundMessage::Reply {
+                        id,
+                        data: ReplyData::OpenPoll { poll_id },
+                    }
+                    .send(&*state.responder, state.encoding()),
+                    Err(code) => state.send_error_code(id, code),
+                };
+            }
+
+            // Initialized; InboundMessageReceived (Vote)
+            (
+                FSM::Initialized,
+                ConnectionMessage::InboundMessageReceived {
+                    message:
+                        InboundMessage {
+                            id,
+                            body:
+                                MessageBody::Vote {
+                                    poll_id,
+                                    option_index,
+                                },
+                        },
+                },
+            ) => {
+                if !state.require_permission(&id, Permission::Vote) {
+                    return Ok(());
+                }
+
+                let session_state = state.session_state.as_ref().unwrap();
+                let room_id = session_state.room_id.clone();
+                let session_id = session_state.session_id.clone();
+                let vote_result = call!(state.server_actor, move |reply_port| {
+                    ServerMessage::Vote {
+                        room_id,
+                        session_id,
+                        poll_id,
+                        option_index,
+                        reply_port,
+                    }
+                })?;
+
+                match vote_result {
+                    Ok(()) => OutboundMessage::Reply {
+                        id,
+                        data: ReplyData::Vote,
+                    }
+                    .send(&*state.responder, state.encoding()),
+                    Err(code) => state.send_error_code(id, code),
+                };
+            }
+
+            // Initialized; InboundMessageReceived (ClosePoll)
+            (
+                FSM::Initialized,
+                ConnectionMessage::InboundMessageReceived {
+                    message:
+                        InboundMessage {
+                            id,
+                            body: MessageBody::ClosePoll { poll_id },
+                        },
+                },
+            ) => {
+                if !state.require_permission(&id, Permission::ManagePoll) {
+                    return Ok(());
+                }
+
+                let room_id = state.session_state.as_ref().unwrap().room_id.clone();
+                let close_result = call!(state.server_actor, move |reply_port| {
+                    ServerMessage::ClosePoll {
+                        room_id,
+                        poll_id,
+                        reply_port,
+                    }
+                })?;
+
+                match close_result {
+                    Ok(()) => OutboundMessage::Reply {
+                        id,
+                        data: ReplyData::ClosePoll,
+                    }
+                    .send(&*state.responder, state.encoding()),
+                    Err(code) => state.send_error_code(id, code),
+                };
+            }
+
+            // Initialized; InboundMessageReceived (SetReadinessBarrier)
+            (
+                FSM::Initialized,
+                ConnectionMessage::InboundMessageReceived {
+                    message:
+                        InboundMessage {
+                            id,
+                            body:
+                                MessageBody::SetReadinessBarrier {
+                                    enabled,
+                                    ignore_dangling,
+                                },
+                        },
+                },
+            ) => {
+                if !state.require_permission(&id, Permission::ManageReadiness) {
+                    return Ok(());
+                }
+
+                let room_id = state.session_state.as_ref().unwrap().room_id.clone();
+
+                state
+                    .server_actor
+                    .send_message(ServerMessage::SetReadinessBarrier {
+                        room_id,
+                        enabled,
+                        ignore_dangling,
+                    })?;
+
+                OutboundMessage::Reply {
+                    id,
+                    data: ReplyData::SetReadinessBarrier,
+                }
+                .send(&*state.responder, state.encoding());
+            }
+
+            // Initialized; InboundMessageReceived (SetReady)
+            (
+                FSM::Initialized,
+                ConnectionMessage::InboundMessageReceived {
+                    message:
+                        InboundMessage {
+                            id,
+                            body: MessageBody::SetReady { ready },
+                        },
+                },
+            ) => {
+                if !state.require_permission(&id, Permission::MarkReady) {
+                    return Ok(());
+                }
+
+                let session_state = state.session_state.as_ref().unwrap();
+                let room_id = session_state.room_id.clone();
+                let session_id = session_state.session_id.clone();
+                let ready_result = call!(state.server_actor, move |reply_port| {
+                    ServerMessage::SetReady {
+                        room_id,
+                        session_id,
+                        ready,
+                        reply_port,
+                    }
+                })?;
+
+                match ready_result {
+                    Ok(()) => OutboundMessage::Reply {
+                        id,
+                        data: ReplyData::SetReady,
+                    }
+                    .send(&*state.responder, state.encoding()),
+                    Err(code) => state.send_error_code(id, code),
+                };
+            }
+
+            // Initialized; InboundMessageReceived (QueueState)
+            (
+                FSM::Initialized,
+                ConnectionMessage::InboundMessageReceived {
+                    message:
+                        InboundMessage {
+                            id,
+                            body: MessageBody::QueueState { state: room_state },
+                        },
+                },
+            ) => {
+                if !state.require_permission(&id, Permission::WriteState) {
+                    return Ok(());
+                }
+
+                if let Err(error) = room_state.validate() {
+                    state.send_error(id, ErrorCode::InvalidState, error.to_string());
+                    return Ok(());
+                }
+
+                let room_id = state.session_state.as_ref().unwrap().room_id.clone();
+
+                state.server_actor.send_message(ServerMessage::QueueState {
+                    room_id,
+                    state: room_state,
+                })?;
+
+                OutboundMessage::Reply {
+                    id,
+                    data: ReplyData::QueueState,
+                }
+                .send(&*state.responder, state.encoding());
+            }
+
+            // Initialized; InboundMessageReceived (ReportProgress)
+            (
+                FSM::Initialized,
+                ConnectionMessage::InboundMessageReceived {
+                    message:
+                        InboundMessage {
+                            id,
+                            body: MessageBody::ReportProgress { progress },
+                        },
+                },
+            ) => {
+                if !state.require_permission(&id, Permission::ReportProgress) {
+                    return Ok(());
+                }
+
+                if let Err(error) = progress.validate() {
+                    state.send_error(id, ErrorCode::InvalidProgress, error.to_string());
+                    return Ok(());
+                }
+
+                let session_state = state.session_state.as_mut().unwrap();
+                session_state.progress = Some(progress.clone());
+
+                state
+                    .server_actor
+                    .send_message(ServerMessage::ReportProgress {
+                        room_id: session_state.room_id.clone(),
+                        session_id: session_state.session_id.clone(),
+                        progress,
+                    })?;
+
+                OutboundMessage::Reply {
+                    id,
+                    data: ReplyData::ReportProgress,
+                }
+                .send(&*state.responder, state.encoding());
+            }
+
+            // Initialized; InboundMessageReceived (SetProfile)
+            (
+                FSM::Initialized,
+                ConnectionMessage::InboundMessageReceived {
+                    message:
+                        InboundMessage {
+                            id,
+                            body: MessageBody::SetProfile { profile },
+                        },
+                },
+            ) => {
+                if let Err(error) = profile.validate() {
+                    state.send_error(id, ErrorCode::InvalidProfile, error.to_string());
+                    return Ok(());
+                }
+
+                let session_state = state.session_state.as_ref().unwrap();
+                let room_id = session_state.room_id.clone();
+                let session_id = session_state.session_id.clone();
+                let new_profile = profile.clone();
+                let profile_result = call!(state.server_actor, move |reply_port| {
+                    ServerMessage::SetProfile {
+                        room_id,
+                        session_id,
+                        profile: new_profile,
+                        reply_port,
+                    }
+                })?;
+
+                match profile_result {
+                    Ok(()) => {
+                        state.session_state.as_mut().unwrap().profile = Some(profile);
+
+                        OutboundMessage::Reply {
+                            id,
+                            data: ReplyData::SetProfile,
+                        }
+                        .send(&*state.responder, state.encoding());
+                    }
+                    Err(code) => state.send_error_code(id, code),
+                };
+            }
+
+            // Initialized; InboundMessageReceived (ListMembers)
+            (
+                FSM::Initialized,
+                ConnectionMessage::InboundMessageReceived {
+                    message:
+                        InboundMessage {
+                            id,
+                            body: MessageBody::ListMembers,
+                        },
+                },
+            ) => {
+                let room_id = state.session_state.as_ref().unwrap().room_id.clone();
+                let members = call!(state.server_actor, move |reply_port| {
+                    ServerMessage::ListMembers {
+                        room_id,
+                        reply_port,
+                    }
+                })?;
+
+                OutboundMessage::Reply {
+                    id,
+                    data: ReplyData::ListMembers { members },
+                }
+                .send(&*state.responder, state.encoding());
+            }
+
+            // Initialized; InboundMessageReceived (TransferHost)
+            (
+                FSM::Initialized,
+                ConnectionMessage::InboundMessageReceived {
+                    message:
+                        InboundMessage {
+                            id,
+                            body: MessageBody::TransferHost { session_id },
+                        },
+                },
+            ) => {
+                if !state.require_permission(&id, Permission::TransferHost) {
+                    return Ok(());
+                }
+
+                let session_state = state.session_state.as_ref().unwrap();
+                let room_id = session_state.room_id.clone();
+                let own_session_id = session_state.session_id.clone();
+                let transfer_result = call!(state.server_actor, move |reply_port| {
+                    ServerMessage::TransferHost {
+                        room_id,
+                        session_id: own_session_id,
+                        new_host_session_id: session_id,
+                        reply_port,
+                    }
+                })?;
+
+                match transfer_result {
+                    Ok(()) => OutboundMessage::Reply {
+                        id,
+                        data: ReplyData::TransferHost,
+                    }
+                    .send(&*state.responder, state.encoding()),
+                    Err(code) => state.send_error_code(id, code),
+                };
+            }
+
+            // Initialized; RoleChanged
+            (FSM::Initialized, ConnectionMessage::RoleChanged { role }) => {
+                state.session_state.as_mut().unwrap().role = role;
+            }
+
+            // Initialized; PushEvent
+            (FSM::Initialized, ConnectionMessage::PushEvent { seq, event }) => {
+                OutboundMessage::Event { seq, data: event }
+                    .send(&*state.responder, state.encoding());
+            }
+
+            // Any state; HeartbeatTick
+            (_, ConnectionMessage::HeartbeatTick) => {
+                let Some(heartbeat) = &mut state.heartbeat else {
+                    return Ok(());
+                };
+
+                if heartbeat.pending_ping.is_some() {
+                    heartbeat.missed_pings += 1;
+
+                    if heartbeat.missed_pings >= state.config.max_missed_heartbeats {
+                        heartbeat.timer_handle.abort();
+                        myself.send_message(ConnectionMessage::Stop {
+                            reason: ConnectionStopReason::HeartbeatTimeout,
+                        })?;
+                        return Ok(());
+                    }
+                }
+
+                let ping_id = heartbeat.next_ping_id;
+                heartbeat.next_ping_id += 1;
+                heartbeat.pending_ping = Some((ping_id, Instant::now()));
+
+                OutboundMessage::Ping {
+                    ping_id,
+                    round_trip_ms: heartbeat
+                        .round_trip_time
+                        .map(|round_trip_time| round_trip_time.as_millis() as u64),
+                }
+                .send(&*state.responder, state.encoding());
+            }
+
+            // Any state; InboundMessageReceived (Pong)
+            (
+                _,
+                ConnectionMessage::InboundMessageReceived {
+                    message:
+                        InboundMessage {
+                            id: _,
+                            body: MessageBody::Pong { ping_id },
+                        },
+                },
+            ) => {
+                let Some(heartbeat) = &mut state.heartbeat else {
+                    return Ok(());
+                };
+
+                // a late pong to an older ping still shows that the client is
+                // alive, it just can't be used to measure the round trip
+                if let Some((pending_ping_id, sent_at)) = heartbeat.pending_ping {
+                    if pending_ping_id == ping_id {
+                        heartbeat.round_trip_time = Some(sent_at.elapsed());
+                    }
+                }
+
+                heartbeat.pending_ping = None;
+                heartbeat.missed_pings = 0;
+            }
+
+            // WaitingForInitialization; InboundMessageReceived (other than Init)
+            (
+                FSM::WaitingForInitialization { timer_handle: _ },
+                ConnectionMessage::InboundMessageReceived {
+                    message: InboundMessage { id, body: _ },
+                },
+            ) => {
+                state.send_error_code(id, ErrorCode::NotInitialized);
+            }
+
+            // Initialized; InboundMessageReceived (Kick)
+            (
+                FSM::Initialized,
+                ConnectionMessage::InboundMessageReceived {
+                    message:
+                        InboundMessage {
+                            id,
+                            body:
+                                MessageBody::Kick {
+                                    session_id: kicked_session_id,
+                                },
+                        },
+                },
+            ) => {
+                if !state.require_permission(&id, Permission::Moderate) {
+                    return Ok(());
+                }
+
+                let session_state = state.session_state.as_ref().unwrap();
+                let room_id = session_state.room_id.clone();
+                let session_id = session_state.session_id.clone();
+                let kick_result = call!(state.server_actor, move |reply_port| {
+                    ServerMessage::KickMember {
+                        room_id,
+                        session_id,
+                        kicked_session_id,
+                        ban: false,
+                        reply_port,
+                    }
+                })?;
+
+                match kick_result {
+                    Ok(()) => OutboundMessage::Reply {
+                        id,
+                        data: ReplyData::Kick,
+                    }
+                    .send(&*state.responder, state.encoding()),
+                    Err(code) => state.send_error_code(id, code),
+                };
+            }
+
+            // Initialized; InboundMessageReceived (Ban)
+            (
+                FSM::Initialized,
+                ConnectionMessage::InboundMessageReceived {
+                    message:
+                        InboundMessage {
+                            id,
+                            body:
+                                MessageBody::Ban {
+                                    session_id: kicked_session_id,
+                                },
+                        },
+                },
+            ) => {
+                if !state.require_permission(&id, Permission::Moderate) {
+                    return Ok(());
+                }
+
+                let session_state = state.session_state.as_ref().unwrap();
+                let room_id = session_state.room_id.clone();
+                let session_id = session_state.session_id.clone();
+                let kick_result = call!(state.server_actor, move |reply_port| {
+                    ServerMessage::KickMember {
+                        room_id,
+                        session_id,
+                        kicked_session_id,
+                        ban: true,
+                        reply_port,
+                    }
+                })?;
+
+                match kick_result {
+                    Ok(()) => OutboundMessage::Reply {
+                        id,
+                        data: ReplyData::Ban,
+                    }
+                    .send(&*state.responder, state.encoding()),
+                    Err(code) => state.send_error_code(id, code),
+                };
+            }
+
+            // Initialized; InboundMessageReceived (CreateInvite)
+            (
+                FSM::Initialized,
+                ConnectionMessage::InboundMessageReceived {
+                    message:
+                        InboundMessage {
+                            id,
+                            body:
+                                MessageBody::CreateInvite {
+                                    role,
+                                    max_uses,
+                                    expires_in_ms,
+                                },
+                        },
+                },
+            ) => {
+                if !state.require_permission(&id, Permission::ManageInvites) {
+                    return Ok(());
+                }
+
+                let error = if role == Role::Host {
+                    Some("invites can't grant the host role".into())
+                } else if max_uses == Some(0) {
+                    Some("max_uses must be at least 1".into())
+                } else if !(1..=MAX_INVITE_LIFETIME_MS).contains(&expires_in_ms) {
+                    Some(format!(
+                        "expires_in_ms must be between 1 and {}",
+                        MAX_INVITE_LIFETIME_MS
+                    ))
+                } else {
+                    None
+                };
+
+                if let Some(error) = error {
+                    state.send_error(id, ErrorCode::InvalidInvite, error);
+                    return Ok(());
+                }
+
+                let session_state = state.session_state.as_ref().unwrap();
+                let room_id = session_state.room_id.clone();
+                let session_id = session_state.session_id.clone();
+                let invite_result = call!(state.server_actor, move |reply_port| {
+                    ServerMessage::CreateInvite {
+                        room_id,
+                        session_id,
+                        role,
+                        max_uses,
+                        lifetime: Duration::from_millis(expires_in_ms),
+                        reply_port,
+                    }
+                })?;
+
+                match invite_result {
+                    Ok((token, expires_at)) => OutboundMessage::Reply {
+                        id,
+                        data: ReplyData::CreateInvite { token, expires_at },
+                    }
+                    .send(&*state.responder, state.encoding()),
+                    Err(code) => state.send_error_code(id, code),
+                };
+            }
+
+            // Initialized; InboundMessageReceived (RevokeInvite)
+            (
+                FSM::Initialized,
+                ConnectionMessage::InboundMessageReceived {
+                    message:
+                        InboundMessage {
+                            id,
+                            body: MessageBody::RevokeInvite { token },
+                        },
+                },
+            ) => {
+                if !state.require_permission(&id, Permission::ManageInvites) {
+                    return Ok(());
+                }
+
+                let session_state = state.session_state.as_ref().unwrap();
+                let room_id = session_state.room_id.clone();
+                let session_id = session_state.session_id.clone();
+                let revoke_result = call!(state.server_actor, move |reply_port| {
+                    ServerMessage::RevokeInvite {
+                        room_id,
+                        session_id,
+                        token,
+                        reply_port,
+                    }
+                })?;
+
+                match revoke_result {
+                    Ok(()) => OutboundMessage::Reply {
+                        id,
+                        data: ReplyData::RevokeInvite,
+                    }
+                    .send(&*state.responder, state.encoding()),
+                    Err(code) => state.send_error_code(id, code),
+                };
+            }
+
+            // Initialized; InboundMessageReceived (Leave)
+            (
+                FSM::Initialized,
+                ConnectionMessage::InboundMessageReceived {
+                    message:
+                        InboundMessage {
+                            id,
+                            body: MessageBody::Leave,
+                        },
+                },
+            ) => {
+                let session_state = state.session_state.as_ref().unwrap();
+                let room_id = session_state.room_id.clone();
+                let session_id = session_state.session_id.clone();
+                let connection_actor = myself.clone();
+                call!(state.server_actor, move |reply_port| {
+                    ServerMessage::LeaveRoom {
+                        room_id,
+                        session_id,
+                        connection_actor,
+                        reply_port,
+                    }
+                })?;
+
+                OutboundMessage::Reply {
+                    id,
+                    data: ReplyData::Leave,
+                }
+                .send(&*state.responder, state.encoding());
+
+                if let Some(heartbeat) = state.heartbeat.take() {
+                    heartbeat.timer_handle.abort();
+                }
+
+                state.session_state = None;
+                state.fsm = FSM::Left;
+
+                myself.send_message(ConnectionMessage::Stop {
+                    reason: ConnectionStopReason::ClientLeft,
+                })?;
+            }
+
+            // Initialized; InboundMessageReceived (Init)
+            (
+                FSM::Initialized,
+                ConnectionMessage::InboundMessageReceived {
+                    message:
+                        InboundMessage {
+                            id,
+                            body: MessageBody::Init(_),
+                        },
+                },
+            ) => {
+                state.send_error_code(id, ErrorCode::AlreadyInitialized);
+            }
+
+            // Any state; InvalidInboundMessageReceived
+            (_, ConnectionMessage::InvalidInboundMessageReceived { id, error }) => {
+                state.send_error(id, ErrorCode::InvalidRequest, error);
             }
 
             // Any state; MalformedInboundMessageReceived
@@ -250,6 +1576,7 @@ impl Actor for ConnectionActor {
                         connection_actor: myself,
                         session_state: state.session_state.clone(),
                         responder: dyn_clone::clone_box(&*state.responder),
+                        encoding: state.encoding(),
                         reason,
                     })?;
             }
@@ -271,12 +1598,12 @@ mock! {
     impl Actor for ConnectionActor {
         type Msg = ConnectionMessage;
         type State = ConnectionState;
-        type Arguments = (ActorRef<ServerActor>, Box<dyn ResponderTrait>);
+        type Arguments = (ActorRef<ServerActor>, Box<dyn ResponderTrait>, ServerConfig);
 
         async fn pre_start(
             &self,
             myself: ActorRef<Self>,
-            args: (ActorRef<ServerActor>, Box<dyn ResponderTrait>)
+            args: (ActorRef<ServerActor>, Box<dyn ResponderTrait>, ServerConfig)
         ) -> Result<ConnectionState, ActorProcessingErr>;
 
         async fn handle(
@@ -1,21 +1,43 @@
 use super::connection_actor::SessionState;
 use crate::{
     actors::connection_actor::{ConnectionActor, ConnectionMessage},
-    messages::inbound::InboundMessage,
-    messages::outbound::OutboundMessage,
-    ResponderDelegate, ResponderTrait,
+    config::{HostMigrationPolicy, ServerConfig},
+    ids::normalize_room_code,
+    messages::encoding::Encoding,
+    messages::inbound::{InboundMessage, InboundMessageId},
+    messages::outbound::{
+        BacklogItem, ChatMessage, ErrorCode, EventData, HistoryEntry, MemberInfo, MemberLeftReason,
+        OutboundMessage, RoomClosedReason,
+    },
+    messages::profile::Profile,
+    messages::state::{ReadingProgress, ReadingState},
+    room::BarrierOutcome,
+    room::{Poll, Role, Room},
+    websocket::Message as WebSocketMessage,
+    ResponderTrait,
 };
 use async_trait::async_trait;
-use mockall::mock;
+use nanoid::nanoid;
 use ractor::{
     concurrency::JoinHandle, Actor, ActorProcessingErr, ActorRef, Message, MessagingErr,
     RpcReplyPort,
 };
-use simple_websockets::Message as WebSocketMessage;
-use std::{collections::HashMap, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 const DANGLING_SESSION_TIMEOUT_MS: Duration = Duration::from_secs(60);
 
+/// Milliseconds since the Unix epoch.
+fn unix_timestamp_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time should be after the Unix epoch")
+        .as_millis() as u64
+}
+
 #[derive(Debug, Clone)]
 pub struct Client {
     pub connection_actor: ActorRef<ConnectionActor>,
@@ -27,10 +49,64 @@ pub struct DanglingSession {
     pub session_state: SessionState,
 }
 
+#[derive(Debug)]
+pub struct ResumedSession {
+    pub session_state: SessionState,
+    pub replay: EventReplay,
+}
+
+/// What a reconnecting session missed while it was away.
+#[derive(Debug)]
+pub enum EventReplay {
+    /// Oldest first, indexed by their sequence number.
+    Events(Vec<HistoryEntry<EventData>>),
+    /// Some of the missed events are no longer kept.
+    Resync,
+}
+
+#[derive(Debug)]
+pub struct Invite {
+    pub room_id: String,
+    pub role: Role,
+    /// Unlimited when `None`.
+    pub remaining_uses: Option<u32>,
+    /// Sessions admitted with the invite, so that it can be revoked if one of
+    /// them gets banned.
+    pub redeemed_by: HashSet<String>,
+    pub timer_handle: JoinHandle<Result<(), MessagingErr>>,
+}
+
+/// Wrong passwords sent for a room, from one address or from everyone.
+#[derive(Debug)]
+pub struct PasswordAttempts {
+    pub window_started_at: Instant,
+    pub failures: u32,
+}
+
+impl PasswordAttempts {
+    fn new() -> Self {
+        PasswordAttempts {
+            window_started_at: Instant::now(),
+            failures: 0,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ServerState {
+    pub config: ServerConfig,
     pub clients: HashMap<u64, Client>,
     pub dangling_sessions: HashMap<String, DanglingSession>,
+    pub rooms: HashMap<String, Room>,
+    /// Deadline timers of the rooms' open polls, keyed by room id.
+    pub poll_timers: HashMap<String, JoinHandle<Result<(), MessagingErr>>>,
+    /// Invites of every room, keyed by token.
+    pub invites: HashMap<String, Invite>,
+    /// Keyed by room id and client address. Clients whose address is unknown
+    /// are counted by their connection actor instead.
+    pub password_attempts: HashMap<(String, IpAddr), PasswordAttempts>,
+    /// Wrong passwords of every client of a room together, keyed by room id.
+    pub room_password_failures: HashMap<String, PasswordAttempts>,
 }
 
 #[derive(Debug)]
@@ -38,6 +114,9 @@ pub struct ServerState {
 pub struct ServerStateSnapshot {
     pub clients: HashMap<u64, Client>,
     pub dangling_sessions: HashMap<String, SessionState>,
+    pub rooms: HashMap<String, Room>,
+    /// Room id of every invite, keyed by token.
+    pub invites: HashMap<String, String>,
 }
 
 #[derive(Debug)]
@@ -45,7 +124,55 @@ pub enum ConnectionStopReason {
     InitTimeout,
     MalformedMessage,
     BadSessionIdProvided,
+    RoomNotFound,
+    RoomFull,
+    InvalidSpectatorToken,
+    InvalidInvite,
+    TooManyPasswordAttempts,
+    DisplayNameTaken,
+    Kicked,
+    Banned,
+    RoomClosed,
     ClientDisconnect,
+    /// The client stopped answering pings, which is handled like a disconnect
+    /// that the client didn't get to report.
+    HeartbeatTimeout,
+    UnsupportedProtocolVersion,
+    /// Another connection reconnected to the session.
+    SessionTakenOver,
+    ClientLeft,
+}
+
+#[derive(Debug)]
+pub struct CreatedRoom {
+    pub session_state: SessionState,
+    pub spectator_token: String,
+    pub max_members: usize,
+}
+
+#[derive(Debug)]
+pub enum JoinRoomError {
+    RoomNotFound,
+    RoomFull,
+    InvalidSpectatorToken,
+    InvalidInvite,
+    Banned,
+    DisplayNameTaken,
+    TooManyPasswordAttempts,
+}
+
+impl From<JoinRoomError> for ConnectionStopReason {
+    fn from(error: JoinRoomError) -> Self {
+        match error {
+            JoinRoomError::RoomNotFound => ConnectionStopReason::RoomNotFound,
+            JoinRoomError::RoomFull => ConnectionStopReason::RoomFull,
+            JoinRoomError::InvalidSpectatorToken => ConnectionStopReason::InvalidSpectatorToken,
+            JoinRoomError::InvalidInvite => ConnectionStopReason::InvalidInvite,
+            JoinRoomError::Banned => ConnectionStopReason::Banned,
+            JoinRoomError::DisplayNameTaken => ConnectionStopReason::DisplayNameTaken,
+            JoinRoomError::TooManyPasswordAttempts => ConnectionStopReason::TooManyPasswordAttempts,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -65,11 +192,191 @@ pub enum ServerMessage {
         connection_actor: ActorRef<ConnectionActor>,
         session_state: Option<SessionState>,
         responder: Box<dyn ResponderTrait>,
+        /// Used for the close message.
+        encoding: Encoding,
         reason: ConnectionStopReason,
     },
-    GetDanglingSession {
+    CreateRoom {
+        /// Capped by the server-wide limit, which is also used when unset.
+        max_members: Option<usize>,
+        /// Hashed by the connection actor, so that the server actor isn't kept
+        /// busy with it.
+        password_hash: Option<String>,
+        connection_actor: ActorRef<ConnectionActor>,
+        client_address: Option<IpAddr>,
+        profile: Option<Profile>,
+        reply_port: RpcReplyPort<CreatedRoom>,
+    },
+    /// Replies with the room's canonical id and the hash of its password, so
+    /// that the connection actor can check a password without keeping the
+    /// server actor busy. Fails with `TooManyPasswordAttempts` while the
+    /// client's address or the room as a whole is out of attempts.
+    GetPasswordHash {
+        room_id: String,
+        client_address: Option<IpAddr>,
+        reply_port: RpcReplyPort<Result<(String, Option<String>), JoinRoomError>>,
+    },
+    /// Replies with whether the client's address and the room have attempts
+    /// left. Expects the canonical room id `GetPasswordHash` replied with.
+    ReportFailedPassword {
+        room_id: String,
+        client_address: Option<IpAddr>,
+        reply_port: RpcReplyPort<bool>,
+    },
+    JoinRoom {
+        room_id: String,
+        /// Joins as a spectator when set, as long as it matches the room's token.
+        spectator_token: Option<String>,
+        /// The hash the connection actor checked the client's password against.
+        /// Clients are only let in while it's still the room's, so that a room
+        /// recreated under the same id isn't joined by mistake. Spectators have
+        /// a token of their own and skip this.
+        password_hash: Option<String>,
+        connection_actor: ActorRef<ConnectionActor>,
+        client_address: Option<IpAddr>,
+        profile: Option<Profile>,
+        reply_port: RpcReplyPort<Result<(SessionState, String), JoinRoomError>>,
+    },
+    RedeemInvite {
+        token: String,
+        connection_actor: ActorRef<ConnectionActor>,
+        client_address: Option<IpAddr>,
+        profile: Option<Profile>,
+        reply_port: RpcReplyPort<Result<(SessionState, String), JoinRoomError>>,
+    },
+    GetRoomStateString {
+        room_id: String,
+        reply_port: RpcReplyPort<String>,
+    },
+    SetRoomStateString {
+        room_id: String,
+        string: String,
+    },
+    GetRoomState {
+        room_id: String,
+        reply_port: RpcReplyPort<Option<ReadingState>>,
+    },
+    SetRoomState {
+        room_id: String,
+        state: ReadingState,
+    },
+    GetBacklog {
+        room_id: String,
+        before: Option<u64>,
+        limit: usize,
+        reply_port: RpcReplyPort<(Vec<HistoryEntry<BacklogItem>>, bool)>,
+    },
+    SendChat {
+        room_id: String,
+        session_id: String,
+        text: String,
+        reply_port: RpcReplyPort<Result<(), ErrorCode>>,
+    },
+    GetChatHistory {
+        room_id: String,
+        before: Option<u64>,
+        limit: usize,
+        reply_port: RpcReplyPort<(Vec<HistoryEntry<ChatMessage>>, bool)>,
+    },
+    OpenPoll {
+        room_id: String,
+        options: Vec<String>,
+        duration: Option<Duration>,
+        reply_port: RpcReplyPort<Result<String, ErrorCode>>,
+    },
+    Vote {
+        room_id: String,
+        session_id: String,
+        poll_id: String,
+        option_index: usize,
+        reply_port: RpcReplyPort<Result<(), ErrorCode>>,
+    },
+    ClosePoll {
+        room_id: String,
+        poll_id: String,
+        reply_port: RpcReplyPort<Result<(), ErrorCode>>,
+    },
+    PollDeadlineReached {
+        room_id: String,
+        poll_id: String,
+    },
+    SetReadinessBarrier {
+        room_id: String,
+        enabled: bool,
+        ignore_dangling: bool,
+    },
+    SetReady {
+        room_id: String,
+        session_id: String,
+        ready: bool,
+        reply_port: RpcReplyPort<Result<(), ErrorCode>>,
+    },
+    QueueState {
+        room_id: String,
+        state: ReadingState,
+    },
+    ReportProgress {
+        room_id: String,
+        session_id: String,
+        progress: ReadingProgress,
+    },
+    SetProfile {
+        room_id: String,
+        session_id: String,
+        profile: Profile,
+        reply_port: RpcReplyPort<Result<(), ErrorCode>>,
+    },
+    ListMembers {
+        room_id: String,
+        reply_port: RpcReplyPort<Vec<MemberInfo>>,
+    },
+    TransferHost {
+        room_id: String,
+        session_id: String,
+        new_host_session_id: String,
+        reply_port: RpcReplyPort<Result<(), ErrorCode>>,
+    },
+    KickMember {
+        room_id: String,
+        session_id: String,
+        kicked_session_id: String,
+        /// Also keeps the member from joining the room again.
+        ban: bool,
+        reply_port: RpcReplyPort<Result<(), ErrorCode>>,
+    },
+    CreateInvite {
+        room_id: String,
+        session_id: String,
+        role: Role,
+        max_uses: Option<u32>,
+        lifetime: Duration,
+        /// Replies with the token and when it expires.
+        reply_port: RpcReplyPort<Result<(String, u64), ErrorCode>>,
+    },
+    RevokeInvite {
+        room_id: String,
+        session_id: String,
+        token: String,
+        reply_port: RpcReplyPort<Result<(), ErrorCode>>,
+    },
+    InviteExpired {
+        token: String,
+    },
+    /// Ends the session right away instead of leaving it dangling.
+    LeaveRoom {
+        room_id: String,
         session_id: String,
-        reply_port: RpcReplyPort<Option<DanglingSession>>,
+        connection_actor: ActorRef<ConnectionActor>,
+        reply_port: RpcReplyPort<()>,
+    },
+    /// Binds the session to a new connection, whether it's dangling or still
+    /// held by another connection, which then gets closed.
+    ResumeSession {
+        session_id: String,
+        /// Nothing is replayed when unset.
+        last_seen_seq: Option<u64>,
+        connection_actor: ActorRef<ConnectionActor>,
+        reply_port: RpcReplyPort<Option<ResumedSession>>,
     },
     RemoveDanglingSession {
         session_id: String,
@@ -84,20 +391,283 @@ impl Message for ServerMessage {}
 
 pub struct ServerActor;
 
+impl ServerActor {
+    /// Moves the host role within the room and keeps the roles of the affected
+    /// sessions in sync, whether they're connected or dangling.
+    fn transfer_host(state: &mut ServerState, room_id: &str, new_host_session_id: &str) {
+        let Some(room) = state.rooms.get_mut(room_id) else {
+            return;
+        };
+
+        let previous_host_session_id = room.host_session_id.clone();
+        room.set_host(new_host_session_id);
+
+        for session_id in [previous_host_session_id.as_str(), new_host_session_id] {
+            let Some(member) = room.members.get(session_id) else {
+                continue;
+            };
+
+            match &member.connection_actor {
+                Some(connection_actor) => {
+                    let _ = connection_actor
+                        .send_message(ConnectionMessage::RoleChanged { role: member.role });
+                }
+                None => {
+                    if let Some(dangling_session) = state.dangling_sessions.get_mut(session_id) {
+                        dangling_session.session_state.role = member.role;
+                    }
+                }
+            }
+        }
+
+        room.broadcast(EventData::HostChanged {
+            session_id: new_host_session_id.into(),
+        });
+        Self::update_readiness(room);
+    }
+
+    /// Moves the room on to the given state and starts a new readiness round.
+    fn advance_state(room: &mut Room, state: ReadingState) {
+        room.set_state(state.clone());
+        room.broadcast(EventData::StateChanged { state });
+        Self::push_progress(room);
+        Self::update_readiness(room);
+    }
+
+    /// Tells the host how far behind each client is, which has to happen
+    /// whenever a client reports progress or the room's state moves on.
+    fn push_progress(room: &mut Room) {
+        room.broadcast_where(
+            |_, member| member.role == Role::Host,
+            EventData::ProgressUpdated {
+                members: room.progress_spread(),
+            },
+        );
+    }
+
+    /// Drops the vote of a member who left, so that only members still in the
+    /// room decide the poll.
+    fn withdraw_vote(room: &mut Room, session_id: &str) {
+        let Some(poll) = &mut room.poll else {
+            return;
+        };
+
+        if poll.votes.remove(session_id).is_none() {
+            return;
+        }
+
+        let poll_id = poll.poll_id.clone();
+        let tallies = poll.tallies();
+        room.broadcast(EventData::PollUpdated { poll_id, tallies });
+    }
+
+    /// Re-evaluates the readiness barrier, which has to happen whenever the
+    /// readiness or the set of readers it waits for changes.
+    fn update_readiness(room: &mut Room) {
+        match room.evaluate_readiness() {
+            Some(BarrierOutcome::Advance(state)) => Self::advance_state(room, state),
+            Some(BarrierOutcome::AllReady) => {
+                room.broadcast_where(|_, member| member.role == Role::Host, EventData::AllReady);
+            }
+            None => {}
+        }
+    }
+
+    /// Adds a member to the room and lets everyone else know about it.
+    /// Returns the member's session along with the room's host session id.
+    fn admit_member(
+        room: &mut Room,
+        session_id: String,
+        role: Role,
+        connection_actor: ActorRef<ConnectionActor>,
+        address: Option<IpAddr>,
+        profile: Option<Profile>,
+    ) -> (SessionState, String) {
+        room.add_member(
+            session_id.clone(),
+            role,
+            connection_actor,
+            address,
+            profile.clone(),
+        );
+        room.broadcast_except(
+            &session_id,
+            EventData::MemberJoined {
+                session_id: session_id.clone(),
+                role,
+                profile: profile.clone(),
+            },
+        );
+
+        (
+            SessionState {
+                session_id,
+                room_id: room.room_id.clone(),
+                role,
+                progress: None,
+                profile,
+            },
+            room.host_session_id.clone(),
+        )
+    }
+
+    /// Removes a member that's gone for good, handing over or closing the room
+    /// if it was the host.
+    fn remove_session(
+        state: &mut ServerState,
+        room_id: &str,
+        session_id: &str,
+        reason: MemberLeftReason,
+    ) {
+        let Some(room) = state.rooms.get_mut(room_id) else {
+            return;
+        };
+
+        let profile = room
+            .remove_member(session_id)
+            .and_then(|member| member.profile);
+        Self::withdraw_vote(room, session_id);
+        room.broadcast(EventData::MemberLeft {
+            session_id: session_id.into(),
+            profile,
+            reason,
+        });
+        Self::push_progress(room);
+        Self::update_readiness(room);
+
+        if room.is_empty() {
+            let room = Self::remove_room(state, room_id).expect("room should exist");
+            println!("Room {} removed", room.room_id);
+        } else if room.host_session_id == session_id {
+            match (
+                state.config.host_migration_policy,
+                room.longest_connected_client(),
+            ) {
+                (HostMigrationPolicy::PromoteLongestConnected, Some(new_host_session_id)) => {
+                    Self::transfer_host(state, room_id, &new_host_session_id);
+                }
+                _ => {
+                    Self::close_room(state, room_id, RoomClosedReason::HostLeft);
+                }
+            }
+        }
+    }
+
+    /// Removes the room along with any timers and invites still around for it.
+    fn remove_room(state: &mut ServerState, room_id: &str) -> Option<Room> {
+        if let Some(timer_handle) = state.poll_timers.remove(room_id) {
+            timer_handle.abort();
+        }
+
+        state.invites.retain(|_, invite| {
+            if invite.room_id != room_id {
+                return true;
+            }
+
+            invite.timer_handle.abort();
+            false
+        });
+
+        state
+            .password_attempts
+            .retain(|(attempted_room_id, _), _| attempted_room_id != room_id);
+        state.room_password_failures.remove(room_id);
+
+        state.rooms.remove(room_id)
+    }
+
+    /// Forgets attempts whose window has passed.
+    fn prune_password_attempts(state: &mut ServerState) {
+        let window = state.config.password_attempt_window;
+
+        state
+            .password_attempts
+            .retain(|_, attempts| attempts.window_started_at.elapsed() < window);
+        state
+            .room_password_failures
+            .retain(|_, attempts| attempts.window_started_at.elapsed() < window);
+    }
+
+    /// Removes the room, disconnecting its connected members and dropping the
+    /// sessions of its dangling ones.
+    fn close_room(state: &mut ServerState, room_id: &str, reason: RoomClosedReason) {
+        let Some(mut room) = Self::remove_room(state, room_id) else {
+            return;
+        };
+
+        room.broadcast(EventData::RoomClosed { reason });
+
+        for (session_id, member) in &room.members {
+            match &member.connection_actor {
+                Some(connection_actor) => {
+                    let _ = connection_actor.send_message(ConnectionMessage::Stop {
+                        reason: ConnectionStopReason::RoomClosed,
+                    });
+                }
+                None => {
+                    if let Some(dangling_session) = state.dangling_sessions.remove(session_id) {
+                        dangling_session.timer_handle.abort();
+                    }
+                }
+            }
+        }
+
+        println!("Room {} closed", room_id);
+    }
+
+    /// Closes the room's open poll, publishing its result and recording it in
+    /// the room's backlog.
+    fn close_poll(state: &mut ServerState, room_id: &str, poll_id: &str) -> Result<(), ErrorCode> {
+        let Some(room) = state.rooms.get_mut(room_id) else {
+            return Err(ErrorCode::RoomNotFound);
+        };
+
+        let is_open = room
+            .poll
+            .as_ref()
+            .is_some_and(|poll| poll.poll_id == poll_id);
+
+        if !is_open {
+            return Err(ErrorCode::PollNotFound);
+        }
+
+        let poll = room.poll.take().expect("poll should be open");
+
+        if let Some(timer_handle) = state.poll_timers.remove(room_id) {
+            timer_handle.abort();
+        }
+
+        room.backlog.push(poll.result());
+        room.broadcast(EventData::PollClosed {
+            poll_id: poll.poll_id.clone(),
+            tallies: poll.tallies(),
+            winner: poll.winner(),
+        });
+
+        Ok(())
+    }
+}
+
 #[async_trait]
 impl Actor for ServerActor {
     type Msg = ServerMessage;
     type State = ServerState;
-    type Arguments = ();
+    type Arguments = ServerConfig;
 
     async fn pre_start(
         &self,
         _myself: ActorRef<Self>,
-        _: (),
+        config: ServerConfig,
     ) -> Result<Self::State, ActorProcessingErr> {
         Ok(ServerState {
+            config,
             clients: HashMap::new(),
             dangling_sessions: HashMap::new(),
+            rooms: HashMap::new(),
+            poll_timers: HashMap::new(),
+            invites: HashMap::new(),
+            password_attempts: HashMap::new(),
+            room_password_failures: HashMap::new(),
         })
     }
 
@@ -107,15 +677,18 @@ impl Actor for ServerActor {
         message: Self::Msg,
         state: &mut Self::State,
     ) -> Result<(), ActorProcessingErr> {
-        println!("message received, {:?}", message);
         match message {
             ServerMessage::Connect {
                 client_id,
                 responder,
             } => {
-                let (actor, _) = Actor::spawn(None, ConnectionActor, (myself.clone(), responder))
-                    .await
-                    .expect("failed to start server actor");
+                let (actor, _) = Actor::spawn(
+                    None,
+                    ConnectionActor,
+                    (myself.clone(), responder, state.config.clone()),
+                )
+                .await
+                .expect("failed to start server actor");
 
                 state.clients.insert(
                     client_id,
@@ -135,23 +708,29 @@ impl Actor for ServerActor {
                 };
             }
             ServerMessage::Message { client_id, message } => {
-                let client = state
-                    .clients
-                    .get(&client_id)
-                    .expect(&format!("no client is associated with id {}", &client_id));
-
-                let WebSocketMessage::Text(message_text) = &message else {
-                    client.connection_actor
-                        .send_message(ConnectionMessage::MalformedInboundMessageReceived)?;
+                // frames the client sent before the server closed its connection
+                // can still arrive after it was removed, and are dropped
+                let Some(client) = state.clients.get(&client_id) else {
                     return Ok(());
                 };
 
-                let deserialization_result = serde_json::from_str::<InboundMessage>(message_text);
+                let deserialization_result = Encoding::decode::<InboundMessage>(&message);
 
-                let Ok(parsed_message) = deserialization_result else {
-                    client.connection_actor
-                        .send_message(ConnectionMessage::MalformedInboundMessageReceived)?;
-                    return Ok(());
+                let parsed_message = match deserialization_result {
+                    Ok(parsed_message) => parsed_message,
+                    Err(error) => {
+                        // a message that still carries a request id is answered with an
+                        // error reply, anything else is treated as a protocol violation
+                        client.connection_actor.send_message(
+                            match Encoding::decode::<InboundMessageId>(&message) {
+                                Ok(InboundMessageId { id }) => {
+                                    ConnectionMessage::InvalidInboundMessageReceived { id, error }
+                                }
+                                Err(_) => ConnectionMessage::MalformedInboundMessageReceived,
+                            },
+                        )?;
+                        return Ok(());
+                    }
                 };
 
                 client.connection_actor.send_message(
@@ -164,21 +743,52 @@ impl Actor for ServerActor {
                 connection_actor,
                 session_state,
                 responder,
+                encoding,
                 reason,
             } => {
                 match reason {
                     ConnectionStopReason::InitTimeout
                     | ConnectionStopReason::MalformedMessage
-                    | ConnectionStopReason::BadSessionIdProvided => {
+                    | ConnectionStopReason::BadSessionIdProvided
+                    | ConnectionStopReason::RoomNotFound
+                    | ConnectionStopReason::RoomFull
+                    | ConnectionStopReason::InvalidSpectatorToken
+                    | ConnectionStopReason::InvalidInvite
+                    | ConnectionStopReason::TooManyPasswordAttempts
+                    | ConnectionStopReason::DisplayNameTaken
+                    | ConnectionStopReason::Kicked
+                    | ConnectionStopReason::Banned
+                    | ConnectionStopReason::RoomClosed
+                    | ConnectionStopReason::UnsupportedProtocolVersion
+                    | ConnectionStopReason::SessionTakenOver
+                    | ConnectionStopReason::ClientLeft => {
                         OutboundMessage::Close {
                             reason: match reason {
                                 ConnectionStopReason::InitTimeout => "init_timeout",
                                 ConnectionStopReason::MalformedMessage => "malformed_message",
+                                ConnectionStopReason::RoomNotFound => "room_not_found",
+                                ConnectionStopReason::RoomFull => "room_full",
+                                ConnectionStopReason::InvalidSpectatorToken => {
+                                    "invalid_spectator_token"
+                                }
+                                ConnectionStopReason::InvalidInvite => "invalid_invite",
+                                ConnectionStopReason::TooManyPasswordAttempts => {
+                                    "too_many_password_attempts"
+                                }
+                                ConnectionStopReason::DisplayNameTaken => "display_name_taken",
+                                ConnectionStopReason::Kicked => "kicked",
+                                ConnectionStopReason::Banned => "banned",
+                                ConnectionStopReason::RoomClosed => "room_closed",
+                                ConnectionStopReason::UnsupportedProtocolVersion => {
+                                    "unsupported_protocol_version"
+                                }
+                                ConnectionStopReason::SessionTakenOver => "session_taken_over",
+                                ConnectionStopReason::ClientLeft => "client_left",
                                 _ => "bad_session_id_provided",
                             }
                             .into(),
                         }
-                        .send(&responder);
+                        .send(&*responder, encoding);
 
                         // removing the client so that the ServerMessage::StopConnection
                         // doesn't get re-emitted (closing the websocket connection from
@@ -187,11 +797,58 @@ impl Actor for ServerActor {
 
                         responder.close();
                     }
-                    ConnectionStopReason::ClientDisconnect => {
+                    ConnectionStopReason::ClientDisconnect
+                    | ConnectionStopReason::HeartbeatTimeout => {
+                        // the websocket is most likely dead already, but it might just
+                        // be stuck, in which case the client should learn why it got cut off
+                        if let ConnectionStopReason::HeartbeatTimeout = reason {
+                            OutboundMessage::Close {
+                                reason: "heartbeat_timeout".into(),
+                            }
+                            .send(&*responder, encoding);
+
+                            state.clients.remove(&responder.client_id());
+                            responder.close();
+                        }
+
                         let Some(session_state) = session_state else {
+                            connection_actor.stop(None);
+                            return Ok(());
+                        };
+
+                        // the room might've been closed while the client was disconnecting,
+                        // in which case there's nothing to come back to
+                        let Some(room) = state.rooms.get_mut(&session_state.room_id) else {
+                            connection_actor.stop(None);
                             return Ok(());
                         };
 
+                        // the same goes for members that were removed or whose session
+                        // was taken over by another connection in the meantime
+                        let is_current_connection = room
+                            .members
+                            .get(&session_state.session_id)
+                            .and_then(|member| member.connection_actor.as_ref())
+                            .is_some_and(|actor| actor.get_id() == connection_actor.get_id());
+
+                        if !is_current_connection {
+                            connection_actor.stop(None);
+                            return Ok(());
+                        }
+
+                        room.set_member_connection(&session_state.session_id, None);
+                        room.broadcast_except(
+                            &session_state.session_id,
+                            EventData::MemberDisconnected {
+                                session_id: session_state.session_id.clone(),
+                                profile: room
+                                    .members
+                                    .get(&session_state.session_id)
+                                    .and_then(|member| member.profile.clone()),
+                            },
+                        );
+                        Self::update_readiness(room);
+
                         let session_id = session_state.session_id.clone();
                         let timer_handle =
                             myself.send_after(DANGLING_SESSION_TIMEOUT_MS, move || {
@@ -209,104 +866,3213 @@ impl Actor for ServerActor {
                             },
                         );
 
-                        println!("started dangling session timer for {}", &session_id);
+                        println!("started dangling session timer for {}", session_id);
                     }
                 };
 
                 connection_actor.stop(None);
                 println!("stopped connection actor");
             }
-            ServerMessage::GetDanglingSession {
-                session_id,
+            ServerMessage::CreateRoom {
+                max_members,
+                password_hash,
+                connection_actor,
+                client_address,
+                profile,
                 reply_port,
             } => {
-                let dangling_session_option = state.dangling_sessions.remove(&session_id);
-                reply_port.send(dangling_session_option)?;
-            }
-            ServerMessage::RemoveDanglingSession { session_id } => {
-                state.dangling_sessions.remove(&session_id);
-                println!("Session {} removed", &session_id);
-            }
-            #[cfg(test)]
-            ServerMessage::GetStateSnapshot { reply_port } => {
-                let result = reply_port.send(ServerStateSnapshot {
-                    clients: state.clients.clone(),
-                    dangling_sessions: state
-                        .dangling_sessions
-                        .iter()
-                        .map(|(key, value)| (key.clone(), value.session_state.clone()))
-                        .collect(),
-                });
-                println!("result: {:?}", result);
-            }
-        }
-
-        Ok(())
-    }
-}
+                let session_id = state.config.session_id_generator.generate();
+                let room_id = loop {
+                    let room_id = state.config.room_id_generator.generate();
 
-#[cfg(test)]
-mod tests {
-    use mockall_double::double;
+                    if !state.rooms.contains_key(&room_id) {
+                        break room_id;
+                    }
+                };
+                let spectator_token = nanoid!();
+                // the host always takes up a slot of its own, even if the server-wide
+                // limit is set below that
+                let max_members = max_members
+                    .unwrap_or(state.config.max_room_members)
+                    .min(state.config.max_room_members)
+                    .max(1);
 
-    #[double]
-    use crate::ResponderDelegate;
+                let mut room = Room::new(
+                    room_id.clone(),
+                    session_id.clone(),
+                    spectator_token.clone(),
+                    max_members,
+                    password_hash,
+                    &state.config,
+                );
+                room.add_member(
+                    session_id.clone(),
+                    Role::Host,
+                    connection_actor,
+                    client_address,
+                    profile.clone(),
+                );
+                state.rooms.insert(room_id.clone(), room);
 
-    use super::*;
-    use ractor::call;
+                reply_port.send(CreatedRoom {
+                    session_state: SessionState {
+                        session_id,
+                        room_id,
+                        role: Role::Host,
+                        progress: None,
+                        profile,
+                    },
+                    spectator_token,
+                    max_members,
+                })?;
+            }
+            ServerMessage::GetPasswordHash {
+                room_id,
+                client_address,
+                reply_port,
+            } => {
+                Self::prune_password_attempts(state);
 
-    #[tokio::test]
-    async fn connect_should_add_client_to_hashmap() {
-        let (mock_responder, actor) = start_actor().await;
-        let state = actor.get_state_snapshot().await;
+                let room_id = normalize_room_code(&room_id);
+                let Some(room) = state.rooms.get(&room_id) else {
+                    reply_port.send(Err(JoinRoomError::RoomNotFound))?;
+                    return Ok(());
+                };
 
-        assert_eq!(state.clients.len(), 0);
+                let is_address_locked_out = client_address
+                    .and_then(|client_address| {
+                        state
+                            .password_attempts
+                            .get(&(room_id.clone(), client_address))
+                    })
+                    .is_some_and(|attempts| {
+                        attempts.failures >= state.config.max_password_attempts
+                    });
+                let is_room_locked_out =
+                    state
+                        .room_password_failures
+                        .get(&room_id)
+                        .is_some_and(|attempts| {
+                            attempts.failures >= state.config.max_room_password_failures
+                        });
+                let is_locked_out = is_address_locked_out || is_room_locked_out;
 
-        actor
-            .send_message(ServerMessage::Connect {
-                client_id: 0,
-                responder: Box::new(mock_responder),
-            })
-            .unwrap();
-        let state = actor.get_state_snapshot().await;
+                if is_locked_out {
+                    reply_port.send(Err(JoinRoomError::TooManyPasswordAttempts))?;
+                    return Ok(());
+                }
 
-        assert_eq!(state.clients.len(), 1);
-    }
+                reply_port.send(Ok((room_id, room.password_hash.clone())))?;
+            }
+            ServerMessage::ReportFailedPassword {
+                room_id,
+                client_address,
+                reply_port,
+            } => {
+                Self::prune_password_attempts(state);
 
-    #[tokio::test]
-    async fn disconnect_should_remove_client_from_hashmap() {
-        let (mock_responder, actor) = start_actor().await;
+                let room_failures = state
+                    .room_password_failures
+                    .entry(room_id.clone())
+                    .or_insert_with(PasswordAttempts::new);
+                room_failures.failures += 1;
+                let room_has_attempts_left =
+                    room_failures.failures < state.config.max_room_password_failures;
 
-        actor
-            .send_message(ServerMessage::Connect {
-                client_id: 0,
-                responder: Box::new(mock_responder),
-            })
-            .unwrap();
-        let state = actor.get_state_snapshot().await;
+                // clients whose address is unknown can't be told apart, and counting
+                // them together would let anyone lock everyone else out of the room
+                let Some(client_address) = client_address else {
+                    reply_port.send(room_has_attempts_left)?;
+                    return Ok(());
+                };
 
-        assert_eq!(state.clients.len(), 1);
+                let attempts = state
+                    .password_attempts
+                    .entry((room_id, client_address))
+                    .or_insert_with(PasswordAttempts::new);
+                attempts.failures += 1;
 
-        actor
-            .send_message(ServerMessage::Disconnect { client_id: 0 })
-            .unwrap();
-        let state = actor.get_state_snapshot().await;
+                reply_port.send(
+                    room_has_attempts_left
+                        && attempts.failures < state.config.max_password_attempts,
+                )?;
+            }
+            ServerMessage::JoinRoom {
+                room_id,
+                spectator_token,
+                password_hash,
+                connection_actor,
+                client_address,
+                profile,
+                reply_port,
+            } => {
+                let room_id = normalize_room_code(&room_id);
+                let Some(room) = state.rooms.get_mut(&room_id) else {
+                    reply_port.send(Err(JoinRoomError::RoomNotFound))?;
+                    return Ok(());
+                };
 
-        assert_eq!(state.clients.len(), 0);
-    }
+                if room.is_banned(client_address) {
+                    reply_port.send(Err(JoinRoomError::Banned))?;
+                    return Ok(());
+                }
 
-    // #[tokio::test]
-    // async fn binary_message_should
+                if let Some(profile) = &profile {
+                    if room.is_display_name_taken(profile, None) {
+                        reply_port.send(Err(JoinRoomError::DisplayNameTaken))?;
+                        return Ok(());
+                    }
+                }
 
-    async fn start_actor() -> (ResponderDelegate, ActorRef<ServerActor>) {
-        let mock_responder = ResponderDelegate::new();
-        let (actor, _) = Actor::spawn(None, ServerActor, ())
-            .await
-            .expect("failed to start server actor");
+                let role = match spectator_token {
+                    Some(spectator_token) if spectator_token != room.spectator_token => {
+                        reply_port.send(Err(JoinRoomError::InvalidSpectatorToken))?;
+                        return Ok(());
+                    }
+                    Some(_) => Role::Spectator,
+                    None if room.password_hash != password_hash => {
+                        reply_port.send(Err(JoinRoomError::RoomNotFound))?;
+                        return Ok(());
+                    }
+                    None if room.is_full() => {
+                        reply_port.send(Err(JoinRoomError::RoomFull))?;
+                        return Ok(());
+                    }
+                    None => Role::Client,
+                };
+
+                let session_id = state.config.session_id_generator.generate();
+                reply_port.send(Ok(Self::admit_member(
+                    room,
+                    session_id,
+                    role,
+                    connection_actor,
+                    client_address,
+                    profile,
+                )))?;
+            }
+            ServerMessage::RedeemInvite {
+                token,
+                connection_actor,
+                client_address,
+                profile,
+                reply_port,
+            } => {
+                let Some(invite) = state.invites.get_mut(&token) else {
+                    reply_port.send(Err(JoinRoomError::InvalidInvite))?;
+                    return Ok(());
+                };
+
+                // invites are removed along with their room, but an invite that
+                // somehow outlived it shouldn't take the server down
+                let Some(room) = state.rooms.get_mut(&invite.room_id) else {
+                    reply_port.send(Err(JoinRoomError::InvalidInvite))?;
+                    return Ok(());
+                };
+
+                if room.is_banned(client_address) {
+                    reply_port.send(Err(JoinRoomError::Banned))?;
+                    return Ok(());
+                }
+
+                if let Some(profile) = &profile {
+                    if room.is_display_name_taken(profile, None) {
+                        reply_port.send(Err(JoinRoomError::DisplayNameTaken))?;
+                        return Ok(());
+                    }
+                }
+
+                if invite.role != Role::Spectator && room.is_full() {
+                    reply_port.send(Err(JoinRoomError::RoomFull))?;
+                    return Ok(());
+                }
+
+                let role = invite.role;
+                let session_id = state.config.session_id_generator.generate();
+                invite.redeemed_by.insert(session_id.clone());
+
+                if let Some(remaining_uses) = &mut invite.remaining_uses {
+                    *remaining_uses -= 1;
+
+                    if *remaining_uses == 0 {
+                        invite.timer_handle.abort();
+                        state.invites.remove(&token);
+                    }
+                }
+
+                reply_port.send(Ok(Self::admit_member(
+                    room,
+                    session_id,
+                    role,
+                    connection_actor,
+                    client_address,
+                    profile,
+                )))?;
+            }
+            ServerMessage::KickMember {
+                room_id,
+                session_id,
+                kicked_session_id,
+                ban,
+                reply_port,
+            } => {
+                let Some(room) = state.rooms.get_mut(&room_id) else {
+                    reply_port.send(Err(ErrorCode::MemberNotFound))?;
+                    return Ok(());
+                };
+
+                // the role on the connection might be stale if the host role was
+                // moved while this request was in flight
+                if room.host_session_id != session_id {
+                    reply_port.send(Err(ErrorCode::PermissionDenied))?;
+                    return Ok(());
+                }
+
+                if kicked_session_id == session_id {
+                    reply_port.send(Err(ErrorCode::MemberNotFound))?;
+                    return Ok(());
+                }
+
+                let Some(member) = room.remove_member(&kicked_session_id) else {
+                    reply_port.send(Err(ErrorCode::MemberNotFound))?;
+                    return Ok(());
+                };
+
+                if ban {
+                    room.ban(&kicked_session_id, member.address);
+
+                    // without a known address, the banned member could simply come
+                    // back with the credentials they joined with, so those stop working
+                    room.spectator_token = nanoid!();
+                    let spectator_token = room.spectator_token.clone();
+                    room.broadcast_where(
+                        |_, member| member.role == Role::Host,
+                        EventData::SpectatorTokenChanged { spectator_token },
+                    );
+
+                    state.invites.retain(|_, invite| {
+                        if !invite.redeemed_by.contains(&kicked_session_id) {
+                            return true;
+                        }
+
+                        invite.timer_handle.abort();
+                        false
+                    });
+                }
+
+                let (stop_reason, left_reason) = if ban {
+                    (ConnectionStopReason::Banned, MemberLeftReason::Banned)
+                } else {
+                    (ConnectionStopReason::Kicked, MemberLeftReason::Kicked)
+                };
+
+                // the session is dropped rather than left dangling, so that there's
+                // nothing to reconnect to
+                match member.connection_actor {
+                    Some(connection_actor) => {
+                        let _ = connection_actor.send_message(ConnectionMessage::Stop {
+                            reason: stop_reason,
+                        });
+                    }
+                    None => {
+                        if let Some(dangling_session) =
+                            state.dangling_sessions.remove(&kicked_session_id)
+                        {
+                            dangling_session.timer_handle.abort();
+                        }
+                    }
+                }
+
+                Self::withdraw_vote(room, &kicked_session_id);
+                room.broadcast(EventData::MemberLeft {
+                    session_id: kicked_session_id,
+                    profile: member.profile,
+                    reason: left_reason,
+                });
+                Self::push_progress(room);
+                Self::update_readiness(room);
+
+                reply_port.send(Ok(()))?;
+            }
+            ServerMessage::CreateInvite {
+                room_id,
+                session_id,
+                role,
+                max_uses,
+                lifetime,
+                reply_port,
+            } => {
+                let Some(room) = state.rooms.get(&room_id) else {
+                    reply_port.send(Err(ErrorCode::RoomNotFound))?;
+                    return Ok(());
+                };
+
+                // the role on the connection might be stale if the host role was
+                // moved while this request was in flight
+                if room.host_session_id != session_id {
+                    reply_port.send(Err(ErrorCode::PermissionDenied))?;
+                    return Ok(());
+                }
+
+                let token = nanoid!();
+                let lifetime_ms = u64::try_from(lifetime.as_millis()).unwrap_or(u64::MAX);
+                let expires_at = unix_timestamp_ms().saturating_add(lifetime_ms);
+
+                let timer_token = token.clone();
+                let timer_handle =
+                    myself.send_after(lifetime, move || ServerMessage::InviteExpired {
+                        token: timer_token.clone(),
+                    });
+
+                state.invites.insert(
+                    token.clone(),
+                    Invite {
+                        room_id,
+                        role,
+                        remaining_uses: max_uses,
+                        redeemed_by: HashSet::new(),
+                        timer_handle,
+                    },
+                );
+
+                reply_port.send(Ok((token, expires_at)))?;
+            }
+            ServerMessage::RevokeInvite {
+                room_id,
+                session_id,
+                token,
+                reply_port,
+            } => {
+                let Some(room) = state.rooms.get(&room_id) else {
+                    reply_port.send(Err(ErrorCode::RoomNotFound))?;
+                    return Ok(());
+                };
+
+                if room.host_session_id != session_id {
+                    reply_port.send(Err(ErrorCode::PermissionDenied))?;
+                    return Ok(());
+                }
+
+                let belongs_to_room = state
+                    .invites
+                    .get(&token)
+                    .is_some_and(|invite| invite.room_id == room_id);
+
+                if !belongs_to_room {
+                    reply_port.send(Err(ErrorCode::InviteNotFound))?;
+                    return Ok(());
+                }
+
+                if let Some(invite) = state.invites.remove(&token) {
+                    invite.timer_handle.abort();
+                }
+
+                reply_port.send(Ok(()))?;
+            }
+            ServerMessage::InviteExpired { token } => {
+                // the invite might've been used up or revoked in the meantime
+                state.invites.remove(&token);
+            }
+            ServerMessage::GetRoomStateString {
+                room_id,
+                reply_port,
+            } => {
+                let string = state
+                    .rooms
+                    .get(&room_id)
+                    .map(|room| room.state_string.clone())
+                    .unwrap_or_default();
+
+                reply_port.send(string)?;
+            }
+            ServerMessage::SetRoomStateString { room_id, string } => {
+                let Some(room) = state.rooms.get_mut(&room_id) else {
+                    return Ok(());
+                };
+
+                room.state_string = string.clone();
+                room.broadcast(EventData::StateStringChanged { string });
+            }
+            ServerMessage::GetRoomState {
+                room_id,
+                reply_port,
+            } => {
+                let room_state = state
+                    .rooms
+                    .get(&room_id)
+                    .and_then(|room| room.state.clone());
+
+                reply_port.send(room_state)?;
+            }
+            ServerMessage::SetRoomState {
+                room_id,
+                state: room_state,
+            } => {
+                let Some(room) = state.rooms.get_mut(&room_id) else {
+                    return Ok(());
+                };
+
+                Self::advance_state(room, room_state);
+            }
+            ServerMessage::SetReadinessBarrier {
+                room_id,
+                enabled,
+                ignore_dangling,
+            } => {
+                let Some(room) = state.rooms.get_mut(&room_id) else {
+                    return Ok(());
+                };
+
+                room.readiness_ignores_dangling = ignore_dangling;
+
+                if enabled {
+                    room.readiness.enable();
+                    Self::update_readiness(room);
+                } else if let Some(queued_state) = room.readiness.disable() {
+                    Self::advance_state(room, queued_state);
+                }
+            }
+            ServerMessage::SetReady {
+                room_id,
+                session_id,
+                ready,
+                reply_port,
+            } => {
+                let Some(room) = state.rooms.get_mut(&room_id) else {
+                    reply_port.send(Err(ErrorCode::RoomNotFound))?;
+                    return Ok(());
+                };
+
+                if !room.readiness.set_ready(&session_id, ready) {
+                    reply_port.send(Err(ErrorCode::ReadinessBarrierDisabled))?;
+                    return Ok(());
+                }
+
+                Self::update_readiness(room);
+                reply_port.send(Ok(()))?;
+            }
+            ServerMessage::QueueState {
+                room_id,
+                state: room_state,
+            } => {
+                let Some(room) = state.rooms.get_mut(&room_id) else {
+                    return Ok(());
+                };
+
+                match room.readiness.queue(room_state) {
+                    Some(room_state) => Self::advance_state(room, room_state),
+                    None => Self::update_readiness(room),
+                }
+            }
+            ServerMessage::GetBacklog {
+                room_id,
+                before,
+                limit,
+                reply_port,
+            } => {
+                let page = state
+                    .rooms
+                    .get(&room_id)
+                    .map(|room| room.backlog.page(before, limit))
+                    .unwrap_or_default();
+
+                reply_port.send(page)?;
+            }
+            ServerMessage::SendChat {
+                room_id,
+                session_id,
+                text,
+                reply_port,
+            } => {
+                let Some(room) = state.rooms.get_mut(&room_id) else {
+                    reply_port.send(Err(ErrorCode::RoomNotFound))?;
+                    return Ok(());
+                };
+
+                // the member might've left while the message was in flight
+                let Some(member) = room.members.get(&session_id) else {
+                    reply_port.send(Err(ErrorCode::MemberNotFound))?;
+                    return Ok(());
+                };
+
+                let profile = member.profile.clone();
+                let entry = room.add_chat_message(ChatMessage {
+                    session_id,
+                    profile,
+                    text,
+                    timestamp: unix_timestamp_ms(),
+                });
+
+                room.broadcast(EventData::ChatMessage(entry));
+                reply_port.send(Ok(()))?;
+            }
+            ServerMessage::GetChatHistory {
+                room_id,
+                before,
+                limit,
+                reply_port,
+            } => {
+                let page = state
+                    .rooms
+                    .get(&room_id)
+                    .map(|room| room.chat.page(before, limit))
+                    .unwrap_or_default();
+
+                reply_port.send(page)?;
+            }
+            ServerMessage::OpenPoll {
+                room_id,
+                options,
+                duration,
+                reply_port,
+            } => {
+                let Some(room) = state.rooms.get_mut(&room_id) else {
+                    reply_port.send(Err(ErrorCode::RoomNotFound))?;
+                    return Ok(());
+                };
+
+                if room.poll.is_some() {
+                    reply_port.send(Err(ErrorCode::PollAlreadyOpen))?;
+                    return Ok(());
+                }
+
+                let poll_id = nanoid!();
+                let deadline = duration.map(|duration| {
+                    let duration_ms = u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+                    unix_timestamp_ms().saturating_add(duration_ms)
+                });
+
+                room.poll = Some(Poll {
+                    poll_id: poll_id.clone(),
+                    options: options.clone(),
+                    votes: HashMap::new(),
+                });
+                room.broadcast(EventData::PollOpened {
+                    poll_id: poll_id.clone(),
+                    options,
+                    deadline,
+                });
+
+                if let Some(duration) = duration {
+                    let timer_room_id = room_id.clone();
+                    let timer_poll_id = poll_id.clone();
+                    let timer_handle =
+                        myself.send_after(duration, move || ServerMessage::PollDeadlineReached {
+                            room_id: timer_room_id.clone(),
+                            poll_id: timer_poll_id.clone(),
+                        });
+
+                    state.poll_timers.insert(room_id, timer_handle);
+                }
+
+                reply_port.send(Ok(poll_id))?;
+            }
+            ServerMessage::Vote {
+                room_id,
+                session_id,
+                poll_id,
+                option_index,
+                reply_port,
+            } => {
+                let Some(room) = state.rooms.get_mut(&room_id) else {
+                    reply_port.send(Err(ErrorCode::RoomNotFound))?;
+                    return Ok(());
+                };
+
+                if !room.members.contains_key(&session_id) {
+                    reply_port.send(Err(ErrorCode::MemberNotFound))?;
+                    return Ok(());
+                }
+
+                let Some(poll) = room.poll.as_mut().filter(|poll| poll.poll_id == poll_id) else {
+                    reply_port.send(Err(ErrorCode::PollNotFound))?;
+                    return Ok(());
+                };
+
+                if option_index >= poll.options.len() {
+                    reply_port.send(Err(ErrorCode::InvalidVote))?;
+                    return Ok(());
+                }
+
+                poll.votes.insert(session_id, option_index);
+                let tallies = poll.tallies();
+
+                room.broadcast(EventData::PollUpdated { poll_id, tallies });
+                reply_port.send(Ok(()))?;
+            }
+            ServerMessage::ClosePoll {
+                room_id,
+                poll_id,
+                reply_port,
+            } => {
+                reply_port.send(Self::close_poll(state, &room_id, &poll_id))?;
+            }
+            ServerMessage::PollDeadlineReached { room_id, poll_id } => {
+                // the poll might've been closed by the host in the meantime
+                let _ = Self::close_poll(state, &room_id, &poll_id);
+            }
+            ServerMessage::ReportProgress {
+                room_id,
+                session_id,
+                progress,
+            } => {
+                let Some(room) = state.rooms.get_mut(&room_id) else {
+                    return Ok(());
+                };
+
+                let Some(member) = room.members.get_mut(&session_id) else {
+                    return Ok(());
+                };
+
+                member.progress = Some(progress);
+                Self::push_progress(room);
+            }
+            ServerMessage::SetProfile {
+                room_id,
+                session_id,
+                profile,
+                reply_port,
+            } => {
+                let Some(room) = state.rooms.get_mut(&room_id) else {
+                    reply_port.send(Err(ErrorCode::MemberNotFound))?;
+                    return Ok(());
+                };
+
+                if room.is_display_name_taken(&profile, Some(&session_id)) {
+                    reply_port.send(Err(ErrorCode::DisplayNameTaken))?;
+                    return Ok(());
+                }
+
+                let Some(member) = room.members.get_mut(&session_id) else {
+                    reply_port.send(Err(ErrorCode::MemberNotFound))?;
+                    return Ok(());
+                };
+
+                member.profile = Some(profile.clone());
+                room.broadcast(EventData::ProfileChanged {
+                    session_id,
+                    profile,
+                });
+                reply_port.send(Ok(()))?;
+            }
+            ServerMessage::ListMembers {
+                room_id,
+                reply_port,
+            } => {
+                let members = state
+                    .rooms
+                    .get(&room_id)
+                    .map(Room::member_infos)
+                    .unwrap_or_default();
+
+                reply_port.send(members)?;
+            }
+            ServerMessage::TransferHost {
+                room_id,
+                session_id,
+                new_host_session_id,
+                reply_port,
+            } => {
+                let Some(room) = state.rooms.get(&room_id) else {
+                    reply_port.send(Err(ErrorCode::MemberNotFound))?;
+                    return Ok(());
+                };
+
+                // the role on the connection might be stale if the host role was
+                // moved while this request was in flight
+                if room.host_session_id != session_id {
+                    reply_port.send(Err(ErrorCode::PermissionDenied))?;
+                    return Ok(());
+                }
+
+                let is_client = room
+                    .members
+                    .get(&new_host_session_id)
+                    .is_some_and(|member| member.role == Role::Client);
+
+                if !is_client {
+                    reply_port.send(Err(ErrorCode::MemberNotFound))?;
+                    return Ok(());
+                }
+
+                Self::transfer_host(state, &room_id, &new_host_session_id);
+                reply_port.send(Ok(()))?;
+            }
+            ServerMessage::ResumeSession {
+                session_id,
+                last_seen_seq,
+                connection_actor,
+                reply_port,
+            } => {
+                let replay_in = |room: &Room| match last_seen_seq {
+                    Some(last_seen_seq) => match room.missed_events(&session_id, last_seen_seq) {
+                        Some(entries) => EventReplay::Events(entries),
+                        None => EventReplay::Resync,
+                    },
+                    None => EventReplay::Events(Vec::new()),
+                };
+
+                // banned sessions are dropped right away, so this only guards
+                // against a ban racing the reconnect
+                let is_banned = state
+                    .rooms
+                    .values()
+                    .any(|room| room.is_session_banned(&session_id));
+
+                if is_banned {
+                    reply_port.send(None)?;
+                    return Ok(());
+                }
+
+                if let Some(dangling_session) = state.dangling_sessions.remove(&session_id) {
+                    dangling_session.timer_handle.abort();
+
+                    let mut replay = EventReplay::Events(Vec::new());
+
+                    if let Some(room) = state.rooms.get_mut(&dangling_session.session_state.room_id)
+                    {
+                        // computed before the reconnect is broadcast, which the
+                        // session doesn't need to hear about
+                        replay = replay_in(room);
+
+                        room.set_member_connection(&session_id, Some(connection_actor));
+                        room.broadcast_except(
+                            &session_id,
+                            EventData::MemberReconnected {
+                                session_id: session_id.clone(),
+                                profile: room
+                                    .members
+                                    .get(&session_id)
+                                    .and_then(|member| member.profile.clone()),
+                            },
+                        );
+                    }
+
+                    reply_port.send(Some(ResumedSession {
+                        session_state: dangling_session.session_state,
+                        replay,
+                    }))?;
+                    return Ok(());
+                }
+
+                let Some(room) = state
+                    .rooms
+                    .values_mut()
+                    .find(|room| room.members.contains_key(&session_id))
+                else {
+                    reply_port.send(None)?;
+                    return Ok(());
+                };
+
+                // the session isn't dangling, so it's still held by a connection
+                // that might not have noticed it's dead yet. Rebinding it here means
+                // that a late disconnect of the old connection is ignored, and that
+                // the rest of the room never sees the member leave
+                let member = &room.members[&session_id];
+                let session_state = SessionState {
+                    session_id: session_id.clone(),
+                    room_id: room.room_id.clone(),
+                    role: member.role,
+                    progress: member.progress.clone(),
+                    profile: member.profile.clone(),
+                };
+                let old_connection_actor = member.connection_actor.clone();
+                let replay = replay_in(room);
+
+                room.set_member_connection(&session_id, Some(connection_actor));
+
+                if let Some(old_connection_actor) = old_connection_actor {
+                    let _ = old_connection_actor.send_message(ConnectionMessage::Stop {
+                        reason: ConnectionStopReason::SessionTakenOver,
+                    });
+                }
+
+                reply_port.send(Some(ResumedSession {
+                    session_state,
+                    replay,
+                }))?;
+            }
+            ServerMessage::RemoveDanglingSession { session_id } => {
+                let Some(dangling_session) = state.dangling_sessions.remove(&session_id) else {
+                    return Ok(());
+                };
+
+                println!("Session {} removed", session_id);
+
+                Self::remove_session(
+                    state,
+                    &dangling_session.session_state.room_id,
+                    &session_id,
+                    MemberLeftReason::SessionExpired,
+                );
+            }
+            ServerMessage::LeaveRoom {
+                room_id,
+                session_id,
+                connection_actor,
+                reply_port,
+            } => {
+                // a connection whose session was taken over has nothing left to leave
+                let holds_session = state
+                    .rooms
+                    .get(&room_id)
+                    .and_then(|room| room.members.get(&session_id))
+                    .and_then(|member| member.connection_actor.as_ref())
+                    .is_some_and(|actor| actor.get_id() == connection_actor.get_id());
+
+                if holds_session {
+                    Self::remove_session(state, &room_id, &session_id, MemberLeftReason::Left);
+                }
+
+                reply_port.send(())?;
+            }
+            #[cfg(test)]
+            ServerMessage::GetStateSnapshot { reply_port } => {
+                let result = reply_port.send(ServerStateSnapshot {
+                    clients: state.clients.clone(),
+                    dangling_sessions: state
+                        .dangling_sessions
+                        .iter()
+                        .map(|(key, value)| (key.clone(), value.session_state.clone()))
+                        .collect(),
+                    rooms: state.rooms.clone(),
+                    invites: state
+                        .invites
+                        .iter()
+                        .map(|(key, value)| (key.clone(), value.room_id.clone()))
+                        .collect(),
+                });
+                println!("result: {:?}", result);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mockall_double::double;
+
+    #[double]
+    use crate::ResponderDelegate;
+
+    use super::*;
+    use crate::{ids::ScriptedIdGenerator, recording_responder};
+    use ractor::call;
+    use serde_json::{json, Value};
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn connect_should_add_client_to_hashmap() {
+        let (mock_responder, actor) = start_actor().await;
+        let state = actor.get_state_snapshot().await;
+
+        assert_eq!(state.clients.len(), 0);
+
+        actor
+            .send_message(ServerMessage::Connect {
+                client_id: 0,
+                responder: Box::new(mock_responder),
+            })
+            .unwrap();
+        let state = actor.get_state_snapshot().await;
+
+        assert_eq!(state.clients.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn disconnect_should_remove_client_from_hashmap() {
+        let (mock_responder, actor) = start_actor().await;
+
+        actor
+            .send_message(ServerMessage::Connect {
+                client_id: 0,
+                responder: Box::new(mock_responder),
+            })
+            .unwrap();
+        let state = actor.get_state_snapshot().await;
+
+        assert_eq!(state.clients.len(), 1);
+
+        actor
+            .send_message(ServerMessage::Disconnect { client_id: 0 })
+            .unwrap();
+        let state = actor.get_state_snapshot().await;
+
+        assert_eq!(state.clients.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn create_room_should_register_room_with_host_as_member() {
+        let (_, actor) = start_actor().await;
+        let connection_actor = start_connection_actor(&actor).await;
+
+        let created_room = call!(actor, |reply_port| ServerMessage::CreateRoom {
+            max_members: None,
+            password_hash: None,
+            connection_actor,
+            client_address: None,
+            profile: None,
+            reply_port
+        })
+        .unwrap();
+        let session_state = created_room.session_state;
+        let state = actor.get_state_snapshot().await;
+
+        let room = state.rooms.get(&session_state.room_id).unwrap();
+        assert_eq!(room.host_session_id, session_state.session_id);
+        assert!(room.members.contains_key(&session_state.session_id));
+        assert_eq!(room.spectator_token, created_room.spectator_token);
+        assert_eq!(room.max_members, ServerConfig::default().max_room_members);
+    }
+
+    #[tokio::test]
+    async fn create_room_should_retry_room_ids_colliding_with_live_rooms() {
+        let (_, actor) = start_actor_with_config(ServerConfig {
+            room_id_generator: Arc::new(ScriptedIdGenerator::new(&["ABC234", "ABC234", "XYZ789"])),
+            ..ServerConfig::default()
+        })
+        .await;
+
+        let first_room = create_room(&actor).await;
+        let second_room = create_room(&actor).await;
+
+        assert_eq!(first_room.room_id, "ABC234");
+        assert_eq!(second_room.room_id, "XYZ789");
+    }
+
+    #[tokio::test]
+    async fn join_room_should_add_member_to_existing_room() {
+        let (_, actor) = start_actor().await;
+        let host_session_state = create_room(&actor).await;
+        let connection_actor = start_connection_actor(&actor).await;
+
+        let room_id = host_session_state.room_id.clone();
+        let (session_state, host_session_id) = call!(actor, |reply_port| ServerMessage::JoinRoom {
+            room_id,
+            spectator_token: None,
+            password_hash: None,
+            connection_actor,
+            client_address: None,
+            profile: None,
+            reply_port
+        })
+        .unwrap()
+        .unwrap();
+        let state = actor.get_state_snapshot().await;
+
+        assert_eq!(session_state.room_id, host_session_state.room_id);
+        assert_eq!(host_session_id, host_session_state.session_id);
+        let room = state.rooms.get(&session_state.room_id).unwrap();
+        assert_eq!(room.members.len(), 2);
+        assert!(room.members.contains_key(&session_state.session_id));
+    }
+
+    #[tokio::test]
+    async fn join_room_should_reject_unknown_room_id() {
+        let (_, actor) = start_actor().await;
+        let connection_actor = start_connection_actor(&actor).await;
+
+        let join_result = call!(actor, |reply_port| ServerMessage::JoinRoom {
+            room_id: "unknown".into(),
+            spectator_token: None,
+            password_hash: None,
+            connection_actor,
+            client_address: None,
+            profile: None,
+            reply_port
+        })
+        .unwrap();
+
+        assert!(matches!(join_result, Err(JoinRoomError::RoomNotFound)));
+    }
+
+    #[tokio::test]
+    async fn set_ready_should_fail_for_closed_room() {
+        let (_, actor) = start_actor().await;
+
+        let result = call!(actor, |reply_port| ServerMessage::SetReady {
+            room_id: "unknown".into(),
+            session_id: "session".into(),
+            ready: true,
+            reply_port
+        })
+        .unwrap();
+
+        assert!(matches!(result, Err(ErrorCode::RoomNotFound)));
+    }
+
+    #[tokio::test]
+    async fn vote_should_fail_for_closed_room() {
+        let (_, actor) = start_actor().await;
+
+        let result = call!(actor, |reply_port| ServerMessage::Vote {
+            room_id: "unknown".into(),
+            session_id: "session".into(),
+            poll_id: "poll".into(),
+            option_index: 0,
+            reply_port
+        })
+        .unwrap();
+
+        assert!(matches!(result, Err(ErrorCode::RoomNotFound)));
+    }
+
+    #[tokio::test]
+    async fn vote_should_fail_for_sessions_outside_the_room() {
+        let (_, actor) = start_actor().await;
+        let session_state = create_room(&actor).await;
+        let poll_id = call!(actor, |reply_port| ServerMessage::OpenPoll {
+            room_id: session_state.room_id.clone(),
+            options: vec!["stay".into(), "leave".into()],
+            duration: None,
+            reply_port
+        })
+        .unwrap()
+        .unwrap();
+
+        let result = call!(actor, |reply_port| ServerMessage::Vote {
+            room_id: session_state.room_id.clone(),
+            session_id: "departed".into(),
+            poll_id,
+            option_index: 0,
+            reply_port
+        })
+        .unwrap();
+
+        assert!(matches!(result, Err(ErrorCode::MemberNotFound)));
+    }
+
+    #[tokio::test]
+    async fn send_chat_should_fail_for_sessions_outside_the_room() {
+        let (_, actor) = start_actor().await;
+        let session_state = create_room(&actor).await;
+
+        let result = call!(actor, |reply_port| ServerMessage::SendChat {
+            room_id: session_state.room_id.clone(),
+            session_id: "departed".into(),
+            text: "still here".into(),
+            reply_port
+        })
+        .unwrap();
+
+        assert!(matches!(result, Err(ErrorCode::MemberNotFound)));
+        let state = actor.get_state_snapshot().await;
+        assert!(state.rooms[&session_state.room_id]
+            .chat
+            .page(None, 10)
+            .0
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn client_should_join_with_lowercase_room_code() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let (room_id, _) = init_host(&actor, &host).await;
+
+        let client = connect(&actor, 1).await;
+        let session_id =
+            init_client(&actor, &client, &format!(" {} ", room_id.to_lowercase())).await;
+
+        let state = actor.get_state_snapshot().await;
+        let members = &state.rooms[&room_id].members;
+        assert!(members.contains_key(&session_id));
+    }
+
+    #[tokio::test]
+    async fn reconnect_should_return_session_to_its_room() {
+        let (_, actor) = start_actor().await;
+        let old_connection_actor = start_connection_actor(&actor).await;
+        let connection_actor = old_connection_actor.clone();
+        let session_state = call!(actor, |reply_port| ServerMessage::CreateRoom {
+            max_members: None,
+            password_hash: None,
+            connection_actor,
+            client_address: None,
+            profile: None,
+            reply_port
+        })
+        .unwrap()
+        .session_state;
+
+        actor
+            .send_message(ServerMessage::StopConnection {
+                connection_actor: old_connection_actor,
+                session_state: Some(session_state.clone()),
+                responder: Box::new(ResponderDelegate::new()),
+                encoding: Encoding::Json,
+                reason: ConnectionStopReason::ClientDisconnect,
+            })
+            .unwrap();
+        let state = actor.get_state_snapshot().await;
+
+        let room = state.rooms.get(&session_state.room_id).unwrap();
+        assert!(room.members[&session_state.session_id]
+            .connection_actor
+            .is_none());
+        assert!(state
+            .dangling_sessions
+            .contains_key(&session_state.session_id));
+
+        let connection_actor = start_connection_actor(&actor).await;
+        let session_id = session_state.session_id.clone();
+        let resumed_session = call!(actor, |reply_port| ServerMessage::ResumeSession {
+            session_id,
+            last_seen_seq: None,
+            connection_actor,
+            reply_port
+        })
+        .unwrap()
+        .unwrap();
+        let state = actor.get_state_snapshot().await;
+
+        assert_eq!(resumed_session.session_state.room_id, session_state.room_id);
+        let room = state.rooms.get(&session_state.room_id).unwrap();
+        assert!(room.members[&session_state.session_id]
+            .connection_actor
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn set_state_string_should_broadcast_to_room_members() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let (room_id, _) = init_host(&actor, &host).await;
+        let client = connect(&actor, 1).await;
+        init_client(&actor, &client, &room_id).await;
+
+        host.request(
+            &actor,
+            json!({"method": "set_state_string", "string": "line 1"}),
+        );
+
+        for connection in [&host, &client] {
+            let event = connection.wait_for_event("state_string_changed").await;
+            assert_eq!(event["data"]["string"], "line 1");
+        }
+
+        let id = client.request(&actor, json!({"method": "get_state_string"}));
+        let reply = client.wait_for_reply(&id).await;
+
+        assert_eq!(reply["data"]["string"], "line 1");
+    }
+
+    #[tokio::test]
+    async fn request_before_init_should_reply_with_not_initialized_error() {
+        let (_, actor) = start_actor().await;
+        let connection = connect(&actor, 0).await;
+
+        let id = connection.request(&actor, json!({"method": "get_state_string"}));
+        let error = connection.wait_for_error(&id).await;
+
+        assert_eq!(error["code"], "not_initialized");
+    }
+
+    #[tokio::test]
+    async fn second_init_should_reply_with_already_initialized_error() {
+        let (_, actor) = start_actor().await;
+        let connection = connect(&actor, 0).await;
+        init_host(&actor, &connection).await;
+
+        let id = connection.request(&actor, json!({"method": "init", "init_type": "host"}));
+        let error = connection.wait_for_error(&id).await;
+
+        assert_eq!(error["code"], "already_initialized");
+    }
+
+    #[tokio::test]
+    async fn unknown_method_should_reply_with_invalid_request_error() {
+        let (_, actor) = start_actor().await;
+        let connection = connect(&actor, 0).await;
+
+        let id = connection.request(&actor, json!({"method": "does_not_exist"}));
+        let error = connection.wait_for_error(&id).await;
+
+        assert_eq!(error["code"], "invalid_request");
+    }
+
+    #[tokio::test]
+    async fn message_without_id_should_close_the_connection() {
+        let (_, actor) = start_actor().await;
+        let connection = connect(&actor, 0).await;
+
+        actor
+            .send_message(ServerMessage::Message {
+                client_id: 0,
+                message: WebSocketMessage::Text("not json".into()),
+            })
+            .unwrap();
+        let close = connection
+            .wait_for(|message| message["method"] == "close")
+            .await;
+
+        assert_eq!(close["reason"], "malformed_message");
+    }
+
+    #[tokio::test]
+    async fn set_state_string_should_be_denied_for_clients() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let (room_id, _) = init_host(&actor, &host).await;
+        let client = connect(&actor, 1).await;
+        init_client(&actor, &client, &room_id).await;
+
+        let id = client.request(
+            &actor,
+            json!({"method": "set_state_string", "string": "line 1"}),
+        );
+        let error = client.wait_for_error(&id).await;
+
+        assert_eq!(error["code"], "permission_denied");
+
+        let id = host.request(&actor, json!({"method": "get_state_string"}));
+        let reply = host.wait_for_reply(&id).await;
+
+        assert_eq!(reply["data"]["string"], "None");
+    }
+
+    #[tokio::test]
+    async fn set_state_should_broadcast_validated_reading_state() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let (room_id, _) = init_host(&actor, &host).await;
+        let client = connect(&actor, 1).await;
+        init_client(&actor, &client, &room_id).await;
+
+        let id = client.request(&actor, json!({"method": "get_state"}));
+        let reply = client.wait_for_reply(&id).await;
+        assert_eq!(reply["data"]["state"], Value::Null);
+
+        let id = host.request(
+            &actor,
+            json!({"method": "set_state", "state": reading_state(3)}),
+        );
+        host.wait_for_reply(&id).await;
+
+        let event = client.wait_for_event("state_changed").await;
+        assert_eq!(event["data"]["state"], reading_state(3));
+
+        let id = client.request(&actor, json!({"method": "get_state"}));
+        let reply = client.wait_for_reply(&id).await;
+        assert_eq!(reply["data"]["state"], reading_state(3));
+    }
+
+    #[tokio::test]
+    async fn set_state_should_reject_invalid_reading_state() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        init_host(&actor, &host).await;
+
+        let mut state = reading_state(3);
+        state["game_id"] = json!("");
+        let id = host.request(&actor, json!({"method": "set_state", "state": state}));
+        let error = host.wait_for_error(&id).await;
+
+        assert_eq!(error["code"], "invalid_state");
+        assert_eq!(error["message"], "game_id must not be empty");
+    }
+
+    #[tokio::test]
+    async fn get_backlog_should_page_through_bounded_state_history() {
+        let (_, actor) = start_actor_with_config(ServerConfig {
+            backlog_size: 3,
+            ..ServerConfig::default()
+        })
+        .await;
+        let host = connect(&actor, 0).await;
+        let (room_id, _) = init_host(&actor, &host).await;
+        let client = connect(&actor, 1).await;
+        init_client(&actor, &client, &room_id).await;
+
+        for line_index in 0..4 {
+            let id = host.request(
+                &actor,
+                json!({"method": "set_state", "state": reading_state(line_index)}),
+            );
+            host.wait_for_reply(&id).await;
+        }
+
+        let id = client.request(
+            &actor,
+            json!({"method": "get_backlog", "before": null, "limit": 2}),
+        );
+        let reply = client.wait_for_reply(&id).await;
+        let entries = reply["data"]["entries"].as_array().unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["index"], 2);
+        assert_eq!(entries[0]["kind"], "state");
+        assert_eq!(entries[0]["state"], reading_state(2));
+        assert_eq!(entries[1]["index"], 3);
+        assert_eq!(reply["data"]["has_more"], true);
+
+        let id = client.request(
+            &actor,
+            json!({"method": "get_backlog", "before": 2, "limit": 2}),
+        );
+        let reply = client.wait_for_reply(&id).await;
+        let entries = reply["data"]["entries"].as_array().unwrap();
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["index"], 1);
+        assert_eq!(reply["data"]["has_more"], false);
+    }
+
+    #[tokio::test]
+    async fn send_chat_should_broadcast_and_record_message() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let (room_id, _) = init_host(&actor, &host).await;
+        let client = connect(&actor, 1).await;
+        let session_id = init_client(&actor, &client, &room_id).await;
+
+        let id = client.request(&actor, json!({"method": "send_chat", "text": "hello"}));
+        client.wait_for_reply(&id).await;
+
+        for connection in [&host, &client] {
+            let event = connection.wait_for_event("chat_message").await;
+            assert_eq!(event["data"]["index"], 0);
+            assert_eq!(event["data"]["session_id"], session_id);
+            assert_eq!(event["data"]["text"], "hello");
+            assert!(event["data"]["timestamp"].as_u64().unwrap() > 0);
+        }
+
+        let id = host.request(
+            &actor,
+            json!({"method": "get_chat_history", "before": null, "limit": 10}),
+        );
+        let reply = host.wait_for_reply(&id).await;
+        let entries = reply["data"]["entries"].as_array().unwrap();
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["text"], "hello");
+    }
+
+    #[tokio::test]
+    async fn send_chat_should_reject_overly_long_messages() {
+        let (_, actor) = start_actor_with_config(ServerConfig {
+            max_chat_message_length: 5,
+            ..ServerConfig::default()
+        })
+        .await;
+        let host = connect(&actor, 0).await;
+        init_host(&actor, &host).await;
+
+        let id = host.request(&actor, json!({"method": "send_chat", "text": "too long"}));
+        let error = host.wait_for_error(&id).await;
+
+        assert_eq!(error["code"], "invalid_chat_message");
+    }
+
+    #[tokio::test]
+    async fn poll_should_tally_votes_and_record_result_in_backlog() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let (room_id, _) = init_host(&actor, &host).await;
+        let client = connect(&actor, 1).await;
+        init_client(&actor, &client, &room_id).await;
+
+        let id = host.request(
+            &actor,
+            json!({"method": "open_poll", "options": ["stay", "leave"], "duration_ms": null}),
+        );
+        let reply = host.wait_for_reply(&id).await;
+        let poll_id = reply["data"]["poll_id"].as_str().unwrap().to_string();
+
+        let event = client.wait_for_event("poll_opened").await;
+        assert_eq!(event["data"]["options"], json!(["stay", "leave"]));
+
+        let id = client.request(
+            &actor,
+            json!({"method": "vote", "poll_id": poll_id, "option_index": 1}),
+        );
+        client.wait_for_reply(&id).await;
+
+        let event = host.wait_for_event("poll_updated").await;
+        assert_eq!(event["data"]["tallies"], json!([0, 1]));
+
+        let id = host.request(&actor, json!({"method": "close_poll", "poll_id": poll_id}));
+        host.wait_for_reply(&id).await;
+
+        let event = client.wait_for_event("poll_closed").await;
+        assert_eq!(event["data"]["winner"], 1);
+
+        let id = client.request(
+            &actor,
+            json!({"method": "get_backlog", "before": null, "limit": 10}),
+        );
+        let reply = client.wait_for_reply(&id).await;
+        let entries = reply["data"]["entries"].as_array().unwrap();
+        assert_eq!(entries[0]["kind"], "poll_result");
+        assert_eq!(entries[0]["tallies"], json!([0, 1]));
+
+        let id = client.request(
+            &actor,
+            json!({"method": "vote", "poll_id": poll_id, "option_index": 0}),
+        );
+        let error = client.wait_for_error(&id).await;
+        assert_eq!(error["code"], "poll_not_found");
+    }
+
+    #[tokio::test]
+    async fn kicked_member_vote_should_no_longer_count() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let (room_id, _) = init_host(&actor, &host).await;
+        let client = connect(&actor, 1).await;
+        let session_id = init_client(&actor, &client, &room_id).await;
+
+        let id = host.request(
+            &actor,
+            json!({"method": "open_poll", "options": ["stay", "leave"], "duration_ms": null}),
+        );
+        let reply = host.wait_for_reply(&id).await;
+        let poll_id = reply["data"]["poll_id"].as_str().unwrap().to_string();
+
+        let id = client.request(
+            &actor,
+            json!({"method": "vote", "poll_id": poll_id, "option_index": 1}),
+        );
+        client.wait_for_reply(&id).await;
+
+        let id = host.request(&actor, json!({"method": "kick", "session_id": session_id}));
+        host.wait_for_reply(&id).await;
+
+        let event = host
+            .wait_for(|message| {
+                message["data"]["event"] == "poll_updated"
+                    && message["data"]["tallies"] == json!([0, 0])
+            })
+            .await;
+        assert_eq!(event["data"]["poll_id"], poll_id.as_str());
+
+        let id = host.request(&actor, json!({"method": "close_poll", "poll_id": poll_id}));
+        host.wait_for_reply(&id).await;
+
+        let event = host.wait_for_event("poll_closed").await;
+        assert_eq!(event["data"]["winner"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn poll_should_close_when_deadline_is_reached() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        init_host(&actor, &host).await;
+
+        let id = host.request(
+            &actor,
+            json!({"method": "open_poll", "options": ["stay", "leave"], "duration_ms": 50}),
+        );
+        host.wait_for_reply(&id).await;
+
+        let event = host.wait_for_event("poll_closed").await;
+        assert_eq!(event["data"]["winner"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn open_poll_should_reject_overly_long_duration() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        init_host(&actor, &host).await;
+
+        let id = host.request(
+            &actor,
+            json!({"method": "open_poll", "options": ["stay", "leave"], "duration_ms": u64::MAX}),
+        );
+        let error = host.wait_for_error(&id).await;
+
+        assert_eq!(error["code"], "invalid_poll");
+    }
+
+    #[tokio::test]
+    async fn readiness_barrier_should_advance_queued_state_once_everyone_is_ready() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let (room_id, _) = init_host(&actor, &host).await;
+        let client = connect(&actor, 1).await;
+        init_client(&actor, &client, &room_id).await;
+
+        let id = host.request(
+            &actor,
+            json!({"method": "set_readiness_barrier", "enabled": true, "ignore_dangling": false}),
+        );
+        host.wait_for_reply(&id).await;
+        let id = host.request(
+            &actor,
+            json!({"method": "set_state", "state": reading_state(1)}),
+        );
+        host.wait_for_reply(&id).await;
+        client.wait_for_event("state_changed").await;
+
+        let id = host.request(
+            &actor,
+            json!({"method": "queue_state", "state": reading_state(2)}),
+        );
+        host.wait_for_reply(&id).await;
+
+        let id = client.request(&actor, json!({"method": "set_ready", "ready": true}));
+        client.wait_for_reply(&id).await;
+        let event = client.wait_for_event("state_changed").await;
+        assert_eq!(event["data"]["state"], reading_state(2));
+
+        let id = client.request(&actor, json!({"method": "set_ready", "ready": true}));
+        client.wait_for_reply(&id).await;
+        host.wait_for_event("all_ready").await;
+        assert!(!client
+            .sent_messages()
+            .iter()
+            .any(|message| message["data"]["event"] == "all_ready"));
+    }
+
+    #[tokio::test]
+    async fn set_ready_should_fail_while_barrier_is_disabled() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let (room_id, _) = init_host(&actor, &host).await;
+        let client = connect(&actor, 1).await;
+        init_client(&actor, &client, &room_id).await;
+
+        let id = client.request(&actor, json!({"method": "set_ready", "ready": true}));
+        let error = client.wait_for_error(&id).await;
+
+        assert_eq!(error["code"], "readiness_barrier_disabled");
+    }
+
+    #[tokio::test]
+    async fn presence_changes_should_be_pushed_to_room_members() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let (room_id, _) = init_host(&actor, &host).await;
+        let client = connect(&actor, 1).await;
+        let session_id = init_client(&actor, &client, &room_id).await;
+
+        let event = host.wait_for_event("member_joined").await;
+        assert_eq!(event["data"]["session_id"], session_id);
+        assert_eq!(event["data"]["role"], "client");
+
+        actor
+            .send_message(ServerMessage::Disconnect { client_id: 1 })
+            .unwrap();
+        let event = host.wait_for_event("member_disconnected").await;
+        assert_eq!(event["data"]["session_id"], session_id);
+
+        let id = host.request(&actor, json!({"method": "list_members"}));
+        let reply = host.wait_for_reply(&id).await;
+        let members = reply["data"]["members"].as_array().unwrap();
+        assert_eq!(members.len(), 2);
+        assert_eq!(members[0]["role"], "host");
+        assert_eq!(members[1]["session_id"], session_id);
+        assert_eq!(members[1]["connected"], false);
+
+        let client = connect(&actor, 2).await;
+        let id = client.request(
+            &actor,
+            json!({"method": "init", "init_type": "reconnect", "session_id": session_id}),
+        );
+        client.wait_for_reply(&id).await;
+        let event = host.wait_for_event("member_reconnected").await;
+        assert_eq!(event["data"]["session_id"], session_id);
+    }
+
+    #[tokio::test]
+    async fn init_should_select_highest_common_protocol_version() {
+        let (_, actor) = start_actor().await;
+        let connection = connect(&actor, 0).await;
+
+        let id = connection.request(
+            &actor,
+            json!({
+                "method": "init",
+                "init_type": "host",
+                "protocol_versions": [1, 2, 99],
+                "capabilities": ["teleportation"],
+            }),
+        );
+        let reply = connection.wait_for_reply(&id).await;
+
+        assert_eq!(reply["data"]["protocol_version"], 2);
+        assert_eq!(reply["data"]["capabilities"], json!([]));
+        assert!(reply["data"]["room_id"].is_string());
+    }
+
+    #[tokio::test]
+    async fn legacy_clients_should_get_first_version_without_pings() {
+        let (_, actor) = start_actor_with_config(ServerConfig {
+            heartbeat_interval: Duration::from_millis(20),
+            max_missed_heartbeats: 2,
+            ..ServerConfig::default()
+        })
+        .await;
+        let connection = connect(&actor, 0).await;
+
+        let id = connection.request(&actor, json!({"method": "init", "init_type": "host"}));
+        let reply = connection.wait_for_reply(&id).await;
+        assert_eq!(reply["data"]["protocol_version"], 1);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert!(connection.sent_messages().is_empty());
+    }
+
+    #[tokio::test]
+    async fn unsupported_protocol_version_should_close_the_connection() {
+        let (_, actor) = start_actor().await;
+        let connection = connect(&actor, 0).await;
+
+        connection.request(
+            &actor,
+            json!({"method": "init", "init_type": "host", "protocol_versions": [99]}),
+        );
+        let close = connection
+            .wait_for(|message| message["method"] == "close")
+            .await;
+        assert_eq!(close["reason"], "unsupported_protocol_version");
+
+        let state = actor.get_state_snapshot().await;
+        assert!(state.rooms.is_empty());
+    }
+
+    #[tokio::test]
+    async fn message_pack_clients_should_get_binary_replies() {
+        let (_, actor) = start_actor().await;
+        let connection = connect(&actor, 0).await;
+
+        let id = connection.request_encoded(
+            &actor,
+            json!({"method": "init", "init_type": "host", "capabilities": ["msgpack"]}),
+            Encoding::MessagePack,
+        );
+        let reply = connection
+            .wait_for(|message| message["binary"]["id"] == id.as_str())
+            .await;
+        assert_eq!(reply["binary"]["data"]["capabilities"], json!(["msgpack"]));
+
+        // the encoding sticks even if the client goes back to text frames
+        let id = connection.request(
+            &actor,
+            json!({"method": "set_state_string", "string": "line 1"}),
+        );
+        connection
+            .wait_for(|message| message["binary"]["id"] == id.as_str())
+            .await;
+        let event = connection
+            .wait_for(|message| message["binary"]["data"]["event"] == "state_string_changed")
+            .await;
+        assert_eq!(event["binary"]["data"]["string"], "line 1");
+    }
+
+    #[tokio::test]
+    async fn failed_message_pack_init_should_get_binary_errors_and_close() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let id = host.request(
+            &actor,
+            json!({"method": "init", "init_type": "host", "password": "tuturu"}),
+        );
+        let reply = host.wait_for_reply(&id).await;
+        let room_id = reply["data"]["room_id"].as_str().unwrap();
+
+        let client = connect(&actor, 1).await;
+        let id = client.request(
+            &actor,
+            json!({"method": "init", "init_type": "client", "room_id": room_id, "password": "wrong", "capabilities": ["msgpack"]}),
+        );
+        let error = client
+            .wait_for(|message| message["binary"]["id"] == id.as_str())
+            .await;
+        assert_eq!(error["binary"]["code"], "invalid_password");
+
+        client.request(
+            &actor,
+            json!({"method": "init", "init_type": "client", "room_id": "unknown", "capabilities": ["msgpack"]}),
+        );
+        let close = client
+            .wait_for(|message| message["binary"]["method"] == "close")
+            .await;
+        assert_eq!(close["binary"]["reason"], "room_not_found");
+    }
+
+    #[tokio::test]
+    async fn binary_frames_should_be_accepted_from_json_clients() {
+        let (_, actor) = start_actor().await;
+        let connection = connect(&actor, 0).await;
+        init_host(&actor, &connection).await;
+
+        let id = connection.request_encoded(
+            &actor,
+            json!({"method": "get_state_string"}),
+            Encoding::MessagePack,
+        );
+        let reply = connection.wait_for_reply(&id).await;
+
+        assert_eq!(reply["data"]["string"], "None");
+    }
+
+    #[tokio::test]
+    async fn unanswered_pings_should_leave_a_dangling_session() {
+        let (_, actor) = start_actor_with_config(ServerConfig {
+            heartbeat_interval: Duration::from_millis(20),
+            max_missed_heartbeats: 2,
+            ..ServerConfig::default()
+        })
+        .await;
+        let host = connect(&actor, 0).await;
+        let id = host.request(
+            &actor,
+            json!({"method": "init", "init_type": "host", "protocol_versions": [2]}),
+        );
+        let reply = host.wait_for_reply(&id).await;
+        let session_id = reply["data"]["session_id"].as_str().unwrap().to_string();
+
+        let ping = host.wait_for(|message| message["method"] == "ping").await;
+        host.request(
+            &actor,
+            json!({"method": "pong", "ping_id": ping["ping_id"]}),
+        );
+        let close = host.wait_for(|message| message["method"] == "close").await;
+        assert_eq!(close["reason"], "heartbeat_timeout");
+
+        let state = actor.get_state_snapshot().await;
+        assert!(state.clients.is_empty());
+        assert!(state.dangling_sessions.contains_key(&session_id));
+    }
+
+    #[tokio::test]
+    async fn clients_that_never_answer_pings_should_time_out() {
+        let (_, actor) = start_actor_with_config(ServerConfig {
+            heartbeat_interval: Duration::from_millis(20),
+            max_missed_heartbeats: 2,
+            ..ServerConfig::default()
+        })
+        .await;
+        let host = connect(&actor, 0).await;
+        let id = host.request(
+            &actor,
+            json!({"method": "init", "init_type": "host", "protocol_versions": [2]}),
+        );
+        host.wait_for_reply(&id).await;
+
+        // the client agreed to answer pings by negotiating version 2
+        let close = host.wait_for(|message| message["method"] == "close").await;
+        assert_eq!(close["reason"], "heartbeat_timeout");
+
+        let state = actor.get_state_snapshot().await;
+        assert!(state.clients.is_empty());
+    }
+
+    #[tokio::test]
+    async fn answered_ping_should_report_round_trip_time_with_next_ping() {
+        // long enough for the pong to arrive before the next ping goes out
+        let (_, actor) = start_actor_with_config(ServerConfig {
+            heartbeat_interval: Duration::from_millis(200),
+            max_missed_heartbeats: 2,
+            ..ServerConfig::default()
+        })
+        .await;
+        let connection = connect(&actor, 0).await;
+        let id = connection.request(
+            &actor,
+            json!({"method": "init", "init_type": "host", "protocol_versions": [2]}),
+        );
+        connection.wait_for_reply(&id).await;
+
+        let ping = connection
+            .wait_for(|message| message["method"] == "ping")
+            .await;
+        assert_eq!(ping["round_trip_ms"], Value::Null);
+
+        connection.request(
+            &actor,
+            json!({"method": "pong", "ping_id": ping["ping_id"]}),
+        );
+        let ping = connection
+            .wait_for(|message| message["method"] == "ping")
+            .await;
+        assert!(ping["round_trip_ms"].is_u64());
+
+        assert!(!connection
+            .sent_messages()
+            .iter()
+            .any(|message| message["method"] == "close"));
+    }
+
+    #[tokio::test]
+    async fn reconnect_should_replay_events_missed_while_dangling() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let (room_id, _) = init_host(&actor, &host).await;
+        let client = connect(&actor, 1).await;
+        let session_id = init_client(&actor, &client, &room_id).await;
+
+        host.request(
+            &actor,
+            json!({"method": "set_state_string", "string": "line 1"}),
+        );
+        let event = client.wait_for_event("state_string_changed").await;
+        let last_seen_seq = event["seq"].as_u64().unwrap();
+
+        actor
+            .send_message(ServerMessage::Disconnect { client_id: 1 })
+            .unwrap();
+        host.wait_for_event("member_disconnected").await;
+
+        for string in ["line 2", "line 3"] {
+            let id = host.request(
+                &actor,
+                json!({"method": "set_state_string", "string": string}),
+            );
+            host.wait_for_reply(&id).await;
+        }
+
+        let client = connect(&actor, 2).await;
+        let id = client.request(
+            &actor,
+            json!({
+                "method": "init",
+                "init_type": "reconnect",
+                "session_id": session_id,
+                "last_seen_seq": last_seen_seq,
+            }),
+        );
+        let reply = client.wait_for_reply(&id).await;
+        assert_eq!(reply["data"]["resync_required"], false);
+
+        let replayed: Vec<Value> = client
+            .sent_messages()
+            .into_iter()
+            .filter(|message| message["method"] == "event")
+            .collect();
+        assert_eq!(replayed.len(), 2);
+        assert_eq!(replayed[0]["data"]["string"], "line 2");
+        assert_eq!(replayed[1]["data"]["string"], "line 3");
+        assert!(replayed[0]["seq"].as_u64().unwrap() > last_seen_seq);
+        assert!(replayed[1]["seq"].as_u64() > replayed[0]["seq"].as_u64());
+    }
+
+    #[tokio::test]
+    async fn events_should_be_numbered_without_gaps_for_each_member() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let (room_id, _) = init_host(&actor, &host).await;
+        let client = connect(&actor, 1).await;
+        init_client(&actor, &client, &room_id).await;
+
+        // progress updates only go to the host, and shouldn't leave gaps for
+        // everyone else
+        for line_index in [1, 2] {
+            host.request(
+                &actor,
+                json!({"method": "set_state", "state": reading_state(line_index)}),
+            );
+            let event = client.wait_for_event("state_changed").await;
+            assert_eq!(event["seq"], line_index);
+        }
+
+        let event = host.wait_for_event("member_joined").await;
+        assert_eq!(event["seq"], 1);
+        let event = host.wait_for_event("progress_updated").await;
+        assert_eq!(event["seq"], 3);
+    }
+
+    #[tokio::test]
+    async fn reconnect_should_take_over_a_live_session() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let (room_id, _) = init_host(&actor, &host).await;
+        let old_client = connect(&actor, 1).await;
+        let session_id = init_client(&actor, &old_client, &room_id).await;
+
+        let client = connect(&actor, 2).await;
+        let id = client.request(
+            &actor,
+            json!({"method": "init", "init_type": "reconnect", "session_id": session_id}),
+        );
+        client.wait_for_reply(&id).await;
+
+        let close = old_client
+            .wait_for(|message| message["method"] == "close")
+            .await;
+        assert_eq!(close["reason"], "session_taken_over");
+
+        // a disconnect the old connection reports late must not leave the
+        // session dangling
+        actor
+            .send_message(ServerMessage::Disconnect { client_id: 1 })
+            .unwrap();
+        let state = actor.get_state_snapshot().await;
+        assert!(!state.clients.contains_key(&1));
+        assert!(state.dangling_sessions.is_empty());
+
+        host.request(
+            &actor,
+            json!({"method": "set_state_string", "string": "line 1"}),
+        );
+        let event = client.wait_for_event("state_string_changed").await;
+        assert_eq!(event["data"]["string"], "line 1");
+
+        let id = host.request(&actor, json!({"method": "list_members"}));
+        let reply = host.wait_for_reply(&id).await;
+        let members = reply["data"]["members"].as_array().unwrap();
+        assert!(members.iter().all(|member| member["connected"] == true));
+        assert!(!host
+            .sent_messages()
+            .iter()
+            .any(|message| message["data"]["event"] == "member_disconnected"));
+    }
+
+    #[tokio::test]
+    async fn reconnect_should_require_resync_once_missed_events_are_dropped() {
+        let (_, actor) = start_actor_with_config(ServerConfig {
+            event_replay_size: 2,
+            ..ServerConfig::default()
+        })
+        .await;
+        let host = connect(&actor, 0).await;
+        let (room_id, _) = init_host(&actor, &host).await;
+        let client = connect(&actor, 1).await;
+        let session_id = init_client(&actor, &client, &room_id).await;
+
+        actor
+            .send_message(ServerMessage::Disconnect { client_id: 1 })
+            .unwrap();
+        host.wait_for_event("member_disconnected").await;
+
+        for string in ["line 1", "line 2", "line 3"] {
+            let id = host.request(
+                &actor,
+                json!({"method": "set_state_string", "string": string}),
+            );
+            host.wait_for_reply(&id).await;
+        }
+
+        let client = connect(&actor, 2).await;
+        let id = client.request(
+            &actor,
+            json!({
+                "method": "init",
+                "init_type": "reconnect",
+                "session_id": session_id,
+                "last_seen_seq": 0,
+            }),
+        );
+        let reply = client.wait_for_reply(&id).await;
+
+        assert_eq!(reply["data"]["resync_required"], true);
+        assert!(client.sent_messages().is_empty());
+    }
+
+    #[tokio::test]
+    async fn reported_progress_should_be_pushed_to_host_and_survive_reconnect() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let (room_id, _) = init_host(&actor, &host).await;
+        let client = connect(&actor, 1).await;
+        let session_id = init_client(&actor, &client, &room_id).await;
+
+        host.request(
+            &actor,
+            json!({"method": "set_state", "state": reading_state(10)}),
+        );
+        host.wait_for_event("progress_updated").await;
+
+        let id = client.request(
+            &actor,
+            json!({
+                "method": "report_progress",
+                "progress": {"line_index": 4, "content_hash": "9f86d081884c7d65"},
+            }),
+        );
+        client.wait_for_reply(&id).await;
+
+        let event = host.wait_for_event("progress_updated").await;
+        let members = event["data"]["members"].as_array().unwrap();
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0]["session_id"], session_id);
+        assert_eq!(members[0]["progress"]["line_index"], 4);
+        assert_eq!(members[0]["lines_behind"], 6);
+
+        actor
+            .send_message(ServerMessage::Disconnect { client_id: 1 })
+            .unwrap();
+        host.wait_for_event("member_disconnected").await;
+
+        let connection_actor = start_connection_actor(&actor).await;
+        let resumed_session = call!(actor, |reply_port| ServerMessage::ResumeSession {
+            session_id,
+            last_seen_seq: None,
+            connection_actor,
+            reply_port
+        })
+        .unwrap()
+        .unwrap();
+
+        let progress = resumed_session.session_state.progress.unwrap();
+        assert_eq!(progress.line_index, 4);
+    }
+
+    #[tokio::test]
+    async fn report_progress_should_reject_invalid_content_hash() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let (room_id, _) = init_host(&actor, &host).await;
+        let client = connect(&actor, 1).await;
+        init_client(&actor, &client, &room_id).await;
+
+        let id = client.request(
+            &actor,
+            json!({
+                "method": "report_progress",
+                "progress": {"line_index": 4, "content_hash": "not hex"},
+            }),
+        );
+        let error = client.wait_for_error(&id).await;
+
+        assert_eq!(error["code"], "invalid_progress");
+    }
+
+    #[tokio::test]
+    async fn spectator_should_follow_room_without_mutating_it() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let id = host.request(&actor, json!({"method": "init", "init_type": "host"}));
+        let reply = host.wait_for_reply(&id).await;
+        let room_id = reply["data"]["room_id"].as_str().unwrap();
+        let token = reply["data"]["spectator_token"].as_str().unwrap();
+        let client = connect(&actor, 1).await;
+        init_client(&actor, &client, room_id).await;
+        let spectator = connect(&actor, 2).await;
+
+        let id = spectator.request(
+            &actor,
+            json!({"method": "init", "init_type": "spectator", "room_id": room_id, "token": token}),
+        );
+        spectator.wait_for_reply(&id).await;
+        let event = host.wait_for_event("member_joined").await;
+        assert_eq!(event["data"]["role"], "client");
+        let event = host.wait_for_event("member_joined").await;
+        assert_eq!(event["data"]["role"], "spectator");
+
+        let id = spectator.request(&actor, json!({"method": "send_chat", "text": "hi"}));
+        let error = spectator.wait_for_error(&id).await;
+        assert_eq!(error["code"], "permission_denied");
+
+        let id = host.request(
+            &actor,
+            json!({"method": "set_readiness_barrier", "enabled": true, "ignore_dangling": false}),
+        );
+        host.wait_for_reply(&id).await;
+        host.request(
+            &actor,
+            json!({"method": "set_state", "state": reading_state(1)}),
+        );
+        spectator.wait_for_event("state_changed").await;
+
+        let id = client.request(&actor, json!({"method": "set_ready", "ready": true}));
+        client.wait_for_reply(&id).await;
+        host.wait_for_event("all_ready").await;
+    }
+
+    #[tokio::test]
+    async fn spectator_with_wrong_token_should_be_disconnected() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let (room_id, _) = init_host(&actor, &host).await;
+        let spectator = connect(&actor, 1).await;
+
+        spectator.request(
+            &actor,
+            json!({"method": "init", "init_type": "spectator", "room_id": room_id, "token": "wrong"}),
+        );
+        let close = spectator
+            .wait_for(|message| message["method"] == "close")
+            .await;
+
+        assert_eq!(close["reason"], "invalid_spectator_token");
+    }
+
+    #[tokio::test]
+    async fn full_room_should_reject_new_clients_but_keep_dangling_slots() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let id = host.request(
+            &actor,
+            json!({"method": "init", "init_type": "host", "max_members": 2}),
+        );
+        let reply = host.wait_for_reply(&id).await;
+        assert_eq!(reply["data"]["max_members"], 2);
+        let room_id = reply["data"]["room_id"].as_str().unwrap();
+        let client = connect(&actor, 1).await;
+        let session_id = init_client(&actor, &client, room_id).await;
+
+        actor
+            .send_message(ServerMessage::Disconnect { client_id: 1 })
+            .unwrap();
+        host.wait_for_event("member_disconnected").await;
+
+        let late_client = connect(&actor, 2).await;
+        late_client.request(
+            &actor,
+            json!({"method": "init", "init_type": "client", "room_id": room_id}),
+        );
+        let close = late_client
+            .wait_for(|message| message["method"] == "close")
+            .await;
+        assert_eq!(close["reason"], "room_full");
+
+        let client = connect(&actor, 3).await;
+        let id = client.request(
+            &actor,
+            json!({"method": "init", "init_type": "reconnect", "session_id": session_id}),
+        );
+        client.wait_for_reply(&id).await;
+    }
+
+    #[tokio::test]
+    async fn password_protected_room_should_let_clients_retry_until_out_of_attempts() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let id = host.request(
+            &actor,
+            json!({"method": "init", "init_type": "host", "password": "tuturu"}),
+        );
+        let reply = host.wait_for_reply(&id).await;
+        let room_id = reply["data"]["room_id"].as_str().unwrap();
+        let state = actor.get_state_snapshot().await;
+        assert_ne!(
+            state.rooms[room_id].password_hash.as_deref(),
+            Some("tuturu")
+        );
+
+        let client = connect_from(&actor, 1, Some(IpAddr::from([10, 0, 0, 1]))).await;
+        let id = client.request(
+            &actor,
+            json!({"method": "init", "init_type": "client", "room_id": room_id, "password": "wrong"}),
+        );
+        let error = client.wait_for_error(&id).await;
+        assert_eq!(error["code"], "invalid_password");
+        let id = client.request(
+            &actor,
+            json!({"method": "init", "init_type": "client", "room_id": room_id, "password": "tuturu"}),
+        );
+        client.wait_for_reply(&id).await;
+
+        let client = connect_from(&actor, 2, Some(IpAddr::from([10, 0, 0, 2]))).await;
+        for _ in 0..2 {
+            let id = client.request(
+                &actor,
+                json!({"method": "init", "init_type": "client", "room_id": room_id}),
+            );
+            client.wait_for_error(&id).await;
+        }
+        client.request(
+            &actor,
+            json!({"method": "init", "init_type": "client", "room_id": room_id}),
+        );
+        let close = client
+            .wait_for(|message| message["method"] == "close")
+            .await;
+        assert_eq!(close["reason"], "too_many_password_attempts");
+    }
+
+    #[tokio::test]
+    async fn password_attempts_should_count_regardless_of_room_code_spelling() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let id = host.request(
+            &actor,
+            json!({"method": "init", "init_type": "host", "password": "tuturu"}),
+        );
+        let reply = host.wait_for_reply(&id).await;
+        let room_id = reply["data"]["room_id"].as_str().unwrap();
+
+        let client = connect_from(&actor, 1, Some(IpAddr::from([10, 0, 0, 1]))).await;
+        for spelling in [room_id.to_lowercase(), format!(" {} ", room_id)] {
+            let id = client.request(
+                &actor,
+                json!({"method": "init", "init_type": "client", "room_id": spelling, "password": "wrong"}),
+            );
+            let error = client.wait_for_error(&id).await;
+            assert_eq!(error["code"], "invalid_password");
+        }
+        client.request(
+            &actor,
+            json!({"method": "init", "init_type": "client", "room_id": format!("{}  ", room_id), "password": "wrong"}),
+        );
+        let close = client
+            .wait_for(|message| message["method"] == "close")
+            .await;
+        assert_eq!(close["reason"], "too_many_password_attempts");
+    }
+
+    #[tokio::test]
+    async fn password_attempts_should_survive_reconnecting_until_window_passes() {
+        let (_, actor) = start_actor_with_config(ServerConfig {
+            max_password_attempts: 1,
+            password_attempt_window: Duration::from_millis(300),
+            ..ServerConfig::default()
+        })
+        .await;
+        let host = connect(&actor, 0).await;
+        let id = host.request(
+            &actor,
+            json!({"method": "init", "init_type": "host", "password": "tuturu"}),
+        );
+        let reply = host.wait_for_reply(&id).await;
+        let room_id = reply["data"]["room_id"].as_str().unwrap();
+        let client_address = Some(IpAddr::from([10, 0, 0, 1]));
+
+        let client = connect_from(&actor, 1, client_address).await;
+        client.request(
+            &actor,
+            json!({"method": "init", "init_type": "client", "room_id": room_id, "password": "wrong"}),
+        );
+        let close = client
+            .wait_for(|message| message["method"] == "close")
+            .await;
+        assert_eq!(close["reason"], "too_many_password_attempts");
+
+        let client = connect_from(&actor, 2, client_address).await;
+        client.request(
+            &actor,
+            json!({"method": "init", "init_type": "client", "room_id": room_id, "password": "tuturu"}),
+        );
+        let close = client
+            .wait_for(|message| message["method"] == "close")
+            .await;
+        assert_eq!(close["reason"], "too_many_password_attempts");
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        let client = connect_from(&actor, 3, client_address).await;
+        let id = client.request(
+            &actor,
+            json!({"method": "init", "init_type": "client", "room_id": room_id, "password": "tuturu"}),
+        );
+        client.wait_for_reply(&id).await;
+    }
+
+    #[tokio::test]
+    async fn password_attempts_from_unknown_addresses_should_not_lock_out_others() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let id = host.request(
+            &actor,
+            json!({"method": "init", "init_type": "host", "password": "tuturu"}),
+        );
+        let reply = host.wait_for_reply(&id).await;
+        let room_id = reply["data"]["room_id"].as_str().unwrap();
+
+        let client = connect(&actor, 1).await;
+        for _ in 0..2 {
+            let id = client.request(
+                &actor,
+                json!({"method": "init", "init_type": "client", "room_id": room_id, "password": "wrong"}),
+            );
+            client.wait_for_error(&id).await;
+        }
+        client.request(
+            &actor,
+            json!({"method": "init", "init_type": "client", "room_id": room_id, "password": "wrong"}),
+        );
+        let close = client
+            .wait_for(|message| message["method"] == "close")
+            .await;
+        assert_eq!(close["reason"], "too_many_password_attempts");
+
+        let client = connect(&actor, 2).await;
+        let id = client.request(
+            &actor,
+            json!({"method": "init", "init_type": "client", "room_id": room_id, "password": "tuturu"}),
+        );
+        client.wait_for_reply(&id).await;
+    }
+
+    #[tokio::test]
+    async fn password_failures_spread_over_addresses_should_use_up_the_room_budget() {
+        let (_, actor) = start_actor_with_config(ServerConfig {
+            max_room_password_failures: 2,
+            ..ServerConfig::default()
+        })
+        .await;
+        let host = connect(&actor, 0).await;
+        let id = host.request(
+            &actor,
+            json!({"method": "init", "init_type": "host", "password": "tuturu"}),
+        );
+        let reply = host.wait_for_reply(&id).await;
+        let room_id = reply["data"]["room_id"].as_str().unwrap();
+
+        for client_id in 1..=2 {
+            let address = IpAddr::from([10, 0, 0, client_id as u8]);
+            let client = connect_from(&actor, client_id, Some(address)).await;
+            client.request(
+                &actor,
+                json!({"method": "init", "init_type": "client", "room_id": room_id, "password": "wrong"}),
+            );
+            client
+                .wait_for(|message| message["method"] == "error" || message["method"] == "close")
+                .await;
+        }
+
+        let client = connect_from(&actor, 3, Some(IpAddr::from([10, 0, 0, 3]))).await;
+        client.request(
+            &actor,
+            json!({"method": "init", "init_type": "client", "room_id": room_id, "password": "tuturu"}),
+        );
+        let close = client
+            .wait_for(|message| message["method"] == "close")
+            .await;
+        assert_eq!(close["reason"], "too_many_password_attempts");
+    }
+
+    #[tokio::test]
+    async fn single_use_invite_should_admit_only_one_member() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        init_host(&actor, &host).await;
+        let id = host.request(
+            &actor,
+            json!({"method": "create_invite", "role": "spectator", "max_uses": 1, "expires_in_ms": 60000}),
+        );
+        let reply = host.wait_for_reply(&id).await;
+        let token = reply["data"]["token"].as_str().unwrap();
+
+        let spectator = connect(&actor, 1).await;
+        let id = spectator.request(
+            &actor,
+            json!({"method": "init", "init_type": "invite", "token": token}),
+        );
+        let reply = spectator.wait_for_reply(&id).await;
+        assert_eq!(reply["data"]["init_type"], "spectator");
+
+        let late_spectator = connect(&actor, 2).await;
+        late_spectator.request(
+            &actor,
+            json!({"method": "init", "init_type": "invite", "token": token}),
+        );
+        let close = late_spectator
+            .wait_for(|message| message["method"] == "close")
+            .await;
+        assert_eq!(close["reason"], "invalid_invite");
+    }
+
+    #[tokio::test]
+    async fn create_invite_should_reject_overly_long_lifetime() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        init_host(&actor, &host).await;
+
+        let id = host.request(
+            &actor,
+            json!({"method": "create_invite", "role": "client", "expires_in_ms": u64::MAX}),
+        );
+        let error = host.wait_for_error(&id).await;
+
+        assert_eq!(error["code"], "invalid_invite");
+    }
+
+    #[tokio::test]
+    async fn invites_should_stop_working_once_expired_or_revoked() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        init_host(&actor, &host).await;
+        let id = host.request(
+            &actor,
+            json!({"method": "create_invite", "role": "client", "expires_in_ms": 20}),
+        );
+        let reply = host.wait_for_reply(&id).await;
+        let expiring_token = reply["data"]["token"].as_str().unwrap().to_string();
+        let id = host.request(
+            &actor,
+            json!({"method": "create_invite", "role": "client", "expires_in_ms": 60000}),
+        );
+        let reply = host.wait_for_reply(&id).await;
+        let revoked_token = reply["data"]["token"].as_str().unwrap().to_string();
+
+        let id = host.request(
+            &actor,
+            json!({"method": "revoke_invite", "token": revoked_token}),
+        );
+        host.wait_for_reply(&id).await;
+        let id = host.request(
+            &actor,
+            json!({"method": "revoke_invite", "token": revoked_token}),
+        );
+        let error = host.wait_for_error(&id).await;
+        assert_eq!(error["code"], "invite_not_found");
+
+        for _ in 0..100 {
+            let state = actor.get_state_snapshot().await;
+
+            if state.invites.is_empty() {
+                break;
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        for (client_id, token) in [(1, expiring_token), (2, revoked_token)] {
+            let client = connect(&actor, client_id).await;
+            client.request(
+                &actor,
+                json!({"method": "init", "init_type": "invite", "token": token}),
+            );
+            let close = client
+                .wait_for(|message| message["method"] == "close")
+                .await;
+            assert_eq!(close["reason"], "invalid_invite");
+        }
+    }
+
+    #[tokio::test]
+    async fn invite_created_right_after_leaving_should_not_outlive_the_room() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        init_host(&actor, &host).await;
+
+        host.request(&actor, json!({"method": "leave"}));
+        let id = host.request(
+            &actor,
+            json!({"method": "create_invite", "role": "client", "expires_in_ms": 60000}),
+        );
+        host.wait_for(|message| message["method"] == "close").await;
+
+        assert!(!host
+            .sent_messages()
+            .iter()
+            .any(|message| message["method"] == "reply" && message["id"] == id.as_str()));
+        let state = actor.get_state_snapshot().await;
+        assert!(state.rooms.is_empty());
+        assert!(state.invites.is_empty());
+
+        // the server actor is still alive to turn invites away
+        let client = connect(&actor, 1).await;
+        client.request(
+            &actor,
+            json!({"method": "init", "init_type": "invite", "token": "stale"}),
+        );
+        let close = client
+            .wait_for(|message| message["method"] == "close")
+            .await;
+        assert_eq!(close["reason"], "invalid_invite");
+    }
+
+    #[tokio::test]
+    async fn kicked_member_should_be_disconnected_without_a_dangling_session() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let (room_id, _) = init_host(&actor, &host).await;
+        let client = connect(&actor, 1).await;
+        let session_id = init_client(&actor, &client, &room_id).await;
+
+        let id = host.request(&actor, json!({"method": "kick", "session_id": session_id}));
+        host.wait_for_reply(&id).await;
+
+        let close = client
+            .wait_for(|message| message["method"] == "close")
+            .await;
+        assert_eq!(close["reason"], "kicked");
+        let event = host.wait_for_event("member_left").await;
+        assert_eq!(event["data"]["session_id"], session_id);
+        assert_eq!(event["data"]["reason"], "kicked");
+
+        let state = actor.get_state_snapshot().await;
+        assert!(!state.rooms[&room_id].members.contains_key(&session_id));
+        assert!(!state.dangling_sessions.contains_key(&session_id));
+
+        let client = connect(&actor, 2).await;
+        init_client(&actor, &client, &room_id).await;
+    }
+
+    #[tokio::test]
+    async fn message_in_flight_after_kick_should_be_dropped() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let (room_id, _) = init_host(&actor, &host).await;
+        let client = connect(&actor, 1).await;
+        let session_id = init_client(&actor, &client, &room_id).await;
+
+        let id = host.request(&actor, json!({"method": "kick", "session_id": session_id}));
+        host.wait_for_reply(&id).await;
+        client
+            .wait_for(|message| message["method"] == "close")
+            .await;
+
+        client.request(&actor, json!({"method": "send_chat", "text": "wait"}));
+
+        // the server actor is still alive and serving the room
+        let id = host.request(&actor, json!({"method": "get_state_string"}));
+        host.wait_for_reply(&id).await;
+        let state = actor.get_state_snapshot().await;
+        assert!(!state.clients.contains_key(&1));
+    }
+
+    #[tokio::test]
+    async fn banned_member_should_not_rejoin_from_the_same_address() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let (room_id, _) = init_host(&actor, &host).await;
+        let banned_address = Some(IpAddr::from([10, 0, 0, 1]));
+        let client = connect_from(&actor, 1, banned_address).await;
+        let session_id = init_client(&actor, &client, &room_id).await;
+
+        let id = host.request(&actor, json!({"method": "ban", "session_id": session_id}));
+        host.wait_for_reply(&id).await;
+        let close = client
+            .wait_for(|message| message["method"] == "close")
+            .await;
+        assert_eq!(close["reason"], "banned");
+
+        let client = connect_from(&actor, 2, banned_address).await;
+        client.request(
+            &actor,
+            json!({"method": "init", "init_type": "client", "room_id": room_id}),
+        );
+        let close = client
+            .wait_for(|message| message["method"] == "close")
+            .await;
+        assert_eq!(close["reason"], "banned");
+
+        let client = connect_from(&actor, 3, Some(IpAddr::from([10, 0, 0, 2]))).await;
+        init_client(&actor, &client, &room_id).await;
+    }
+
+    #[tokio::test]
+    async fn ban_should_revoke_the_credentials_the_member_joined_with() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let id = host.request(&actor, json!({"method": "init", "init_type": "host"}));
+        let reply = host.wait_for_reply(&id).await;
+        let room_id = reply["data"]["room_id"].as_str().unwrap().to_string();
+        let old_spectator_token = reply["data"]["spectator_token"].clone();
+
+        let id = host.request(
+            &actor,
+            json!({"method": "create_invite", "role": "client", "expires_in_ms": 60000}),
+        );
+        let token = host.wait_for_reply(&id).await["data"]["token"].clone();
+
+        // no address is known, as in production
+        let client = connect(&actor, 1).await;
+        let id = client.request(
+            &actor,
+            json!({"method": "init", "init_type": "invite", "token": token}),
+        );
+        let session_id = client.wait_for_reply(&id).await["data"]["session_id"].clone();
+
+        let id = host.request(&actor, json!({"method": "ban", "session_id": session_id}));
+        host.wait_for_reply(&id).await;
+        let event = host.wait_for_event("spectator_token_changed").await;
+        let new_spectator_token = event["data"]["spectator_token"].clone();
+        assert_ne!(new_spectator_token, old_spectator_token);
+
+        let client = connect(&actor, 2).await;
+        client.request(
+            &actor,
+            json!({"method": "init", "init_type": "invite", "token": token}),
+        );
+        let close = client
+            .wait_for(|message| message["method"] == "close")
+            .await;
+        assert_eq!(close["reason"], "invalid_invite");
+
+        let client = connect(&actor, 3).await;
+        client.request(
+            &actor,
+            json!({"method": "init", "init_type": "spectator", "room_id": room_id, "token": old_spectator_token}),
+        );
+        let close = client
+            .wait_for(|message| message["method"] == "close")
+            .await;
+        assert_eq!(close["reason"], "invalid_spectator_token");
+
+        let client = connect(&actor, 4).await;
+        let id = client.request(
+            &actor,
+            json!({"method": "init", "init_type": "spectator", "room_id": room_id, "token": new_spectator_token}),
+        );
+        client.wait_for_reply(&id).await;
+
+        let client = connect(&actor, 5).await;
+        client.request(
+            &actor,
+            json!({"method": "init", "init_type": "reconnect", "session_id": session_id}),
+        );
+        let close = client
+            .wait_for(|message| message["method"] == "close")
+            .await;
+        assert_eq!(close["reason"], "bad_session_id_provided");
+    }
+
+    #[tokio::test]
+    async fn kick_should_be_denied_for_clients() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let (room_id, host_session_id) = init_host(&actor, &host).await;
+        let client = connect(&actor, 1).await;
+        init_client(&actor, &client, &room_id).await;
+
+        let id = client.request(
+            &actor,
+            json!({"method": "kick", "session_id": host_session_id}),
+        );
+        let error = client.wait_for_error(&id).await;
+
+        assert_eq!(error["code"], "permission_denied");
+    }
+
+    #[tokio::test]
+    async fn profiles_should_be_unique_and_shown_in_presence_and_chat() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let id = host.request(
+            &actor,
+            json!({"method": "init", "init_type": "host", "profile": {"display_name": "Okabe"}}),
+        );
+        let reply = host.wait_for_reply(&id).await;
+        let room_id = reply["data"]["room_id"].as_str().unwrap();
+
+        let impostor = connect(&actor, 1).await;
+        impostor.request(
+            &actor,
+            json!({
+                "method": "init",
+                "init_type": "client",
+                "room_id": room_id,
+                "profile": {"display_name": "OKABE"},
+            }),
+        );
+        let close = impostor
+            .wait_for(|message| message["method"] == "close")
+            .await;
+        assert_eq!(close["reason"], "display_name_taken");
+
+        let client = connect(&actor, 2).await;
+        let profile = json!({"display_name": "Mayuri", "color": "#4488ff", "avatar_id": "tuturu"});
+        let id = client.request(
+            &actor,
+            json!({"method": "init", "init_type": "client", "room_id": room_id, "profile": profile}),
+        );
+        let reply = client.wait_for_reply(&id).await;
+        let session_id = reply["data"]["session_id"].as_str().unwrap();
+        let event = host.wait_for_event("member_joined").await;
+        assert_eq!(event["data"]["profile"], profile);
+
+        client.request(&actor, json!({"method": "send_chat", "text": "Tutturu!"}));
+        let event = host.wait_for_event("chat_message").await;
+        assert_eq!(event["data"]["profile"]["display_name"], "Mayuri");
+
+        let id = host.request(&actor, json!({"method": "list_members"}));
+        let reply = host.wait_for_reply(&id).await;
+        assert_eq!(
+            reply["data"]["members"][0]["profile"]["display_name"],
+            "Okabe"
+        );
+        assert_eq!(reply["data"]["members"][1]["profile"], profile);
+
+        actor
+            .send_message(ServerMessage::Disconnect { client_id: 2 })
+            .unwrap();
+        let event = host.wait_for_event("member_disconnected").await;
+        assert_eq!(event["data"]["profile"], profile);
+
+        let client = connect(&actor, 3).await;
+        let id = client.request(
+            &actor,
+            json!({"method": "init", "init_type": "reconnect", "session_id": session_id}),
+        );
+        client.wait_for_reply(&id).await;
+        let event = host.wait_for_event("member_reconnected").await;
+        assert_eq!(event["data"]["profile"], profile);
+
+        client.request(&actor, json!({"method": "leave"}));
+        let event = host.wait_for_event("member_left").await;
+        assert_eq!(event["data"]["profile"], profile);
+    }
+
+    #[tokio::test]
+    async fn set_profile_should_broadcast_and_survive_reconnect() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let (room_id, _) = init_host(&actor, &host).await;
+        let id = host.request(
+            &actor,
+            json!({"method": "set_profile", "profile": {"display_name": "Okabe"}}),
+        );
+        host.wait_for_reply(&id).await;
+        host.wait_for_event("profile_changed").await;
+        let client = connect(&actor, 1).await;
+        let session_id = init_client(&actor, &client, &room_id).await;
+
+        let id = client.request(
+            &actor,
+            json!({"method": "set_profile", "profile": {"display_name": "okabe"}}),
+        );
+        let error = client.wait_for_error(&id).await;
+        assert_eq!(error["code"], "display_name_taken");
+        let id = client.request(
+            &actor,
+            json!({"method": "set_profile", "profile": {"display_name": "Daru", "color": "blue"}}),
+        );
+        let error = client.wait_for_error(&id).await;
+        assert_eq!(error["code"], "invalid_profile");
+
+        let id = client.request(
+            &actor,
+            json!({"method": "set_profile", "profile": {"display_name": "Daru"}}),
+        );
+        client.wait_for_reply(&id).await;
+        let event = host.wait_for_event("profile_changed").await;
+        assert_eq!(event["data"]["session_id"], session_id);
+        assert_eq!(event["data"]["profile"]["display_name"], "Daru");
+
+        actor
+            .send_message(ServerMessage::Disconnect { client_id: 1 })
+            .unwrap();
+        host.wait_for_event("member_disconnected").await;
+        let state = actor.get_state_snapshot().await;
+
+        let profile = state.dangling_sessions[&session_id].profile.as_ref();
+        assert_eq!(profile.unwrap().display_name, "Daru");
+    }
+
+    #[tokio::test]
+    async fn invalid_profile_at_init_should_leave_connection_waiting() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+
+        let id = host.request(
+            &actor,
+            json!({"method": "init", "init_type": "host", "profile": {"display_name": ""}}),
+        );
+        let error = host.wait_for_error(&id).await;
+        assert_eq!(error["code"], "invalid_profile");
+
+        init_host(&actor, &host).await;
+    }
+
+    #[tokio::test]
+    async fn host_should_not_exceed_server_wide_member_cap() {
+        let (_, actor) = start_actor_with_config(ServerConfig {
+            max_room_members: 3,
+            ..ServerConfig::default()
+        })
+        .await;
+        let host = connect(&actor, 0).await;
+
+        let id = host.request(
+            &actor,
+            json!({"method": "init", "init_type": "host", "max_members": 10}),
+        );
+        let reply = host.wait_for_reply(&id).await;
+
+        assert_eq!(reply["data"]["max_members"], 3);
+    }
+
+    #[tokio::test]
+    async fn zero_member_cap_should_still_let_hosts_create_rooms() {
+        let (_, actor) = start_actor_with_config(ServerConfig {
+            max_room_members: 0,
+            ..ServerConfig::default()
+        })
+        .await;
+        let host = connect(&actor, 0).await;
+
+        let id = host.request(&actor, json!({"method": "init", "init_type": "host"}));
+        let reply = host.wait_for_reply(&id).await;
+
+        assert_eq!(reply["data"]["max_members"], 1);
+    }
+
+    #[tokio::test]
+    async fn transfer_host_should_swap_roles_of_host_and_client() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let (room_id, _) = init_host(&actor, &host).await;
+        let client = connect(&actor, 1).await;
+        let session_id = init_client(&actor, &client, &room_id).await;
+
+        let id = host.request(
+            &actor,
+            json!({"method": "transfer_host", "session_id": session_id}),
+        );
+        host.wait_for_reply(&id).await;
+
+        for connection in [&host, &client] {
+            let event = connection.wait_for_event("host_changed").await;
+            assert_eq!(event["data"]["session_id"], session_id);
+        }
+
+        let id = client.request(
+            &actor,
+            json!({"method": "set_state_string", "string": "line 1"}),
+        );
+        client.wait_for_reply(&id).await;
+
+        let id = host.request(
+            &actor,
+            json!({"method": "set_state_string", "string": "line 2"}),
+        );
+        let error = host.wait_for_error(&id).await;
+        assert_eq!(error["code"], "permission_denied");
+    }
+
+    #[tokio::test]
+    async fn transfer_host_should_reject_unknown_session() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        init_host(&actor, &host).await;
+
+        let id = host.request(
+            &actor,
+            json!({"method": "transfer_host", "session_id": "unknown"}),
+        );
+        let error = host.wait_for_error(&id).await;
+
+        assert_eq!(error["code"], "member_not_found");
+    }
+
+    #[tokio::test]
+    async fn leave_should_end_the_session_without_a_dangling_period() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let (room_id, _) = init_host(&actor, &host).await;
+        let client = connect(&actor, 1).await;
+        let session_id = init_client(&actor, &client, &room_id).await;
+
+        let id = client.request(&actor, json!({"method": "leave"}));
+        client.wait_for_reply(&id).await;
+        let close = client
+            .wait_for(|message| message["method"] == "close")
+            .await;
+        assert_eq!(close["reason"], "client_left");
+
+        let event = host.wait_for_event("member_left").await;
+        assert_eq!(event["data"]["session_id"], session_id);
+        assert_eq!(event["data"]["reason"], "left");
+
+        let state = actor.get_state_snapshot().await;
+        assert!(state.dangling_sessions.is_empty());
+        assert!(!state.clients.contains_key(&1));
+        assert!(!state.rooms[&room_id].members.contains_key(&session_id));
+
+        // the session can't be reconnected to afterwards
+        let client = connect(&actor, 2).await;
+        client.request(
+            &actor,
+            json!({"method": "init", "init_type": "reconnect", "session_id": session_id}),
+        );
+        let close = client
+            .wait_for(|message| message["method"] == "close")
+            .await;
+        assert_eq!(close["reason"], "bad_session_id_provided");
+    }
+
+    #[tokio::test]
+    async fn requests_sent_after_leave_should_be_dropped() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let (room_id, _) = init_host(&actor, &host).await;
+        let client = connect(&actor, 1).await;
+        init_client(&actor, &client, &room_id).await;
+
+        client.request(&actor, json!({"method": "leave"}));
+        let id = client.request(&actor, json!({"method": "send_chat", "text": "still here"}));
+        client
+            .wait_for(|message| message["method"] == "close")
+            .await;
+        host.wait_for_event("member_left").await;
+
+        assert!(!client
+            .sent_messages()
+            .iter()
+            .any(|message| message["id"] == id.as_str()));
+        let id = host.request(
+            &actor,
+            json!({"method": "get_chat_history", "before": null, "limit": 10}),
+        );
+        let reply = host.wait_for_reply(&id).await;
+        assert_eq!(reply["data"]["entries"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn leaving_host_should_hand_over_the_room() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let (room_id, _) = init_host(&actor, &host).await;
+        let client = connect(&actor, 1).await;
+        let session_id = init_client(&actor, &client, &room_id).await;
+
+        let id = host.request(&actor, json!({"method": "leave"}));
+        host.wait_for_reply(&id).await;
+
+        let event = client.wait_for_event("host_changed").await;
+        assert_eq!(event["data"]["session_id"], session_id);
+
+        let state = actor.get_state_snapshot().await;
+        assert_eq!(state.rooms[&room_id].host_session_id, session_id);
+        assert_eq!(state.rooms[&room_id].members.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn expired_host_session_should_promote_longest_connected_client() {
+        let (_, actor) = start_actor().await;
+        let host = connect(&actor, 0).await;
+        let (room_id, host_session_id) = init_host(&actor, &host).await;
+        let first_client = connect(&actor, 1).await;
+        let session_id = init_client(&actor, &first_client, &room_id).await;
+        let second_client = connect(&actor, 2).await;
+        init_client(&actor, &second_client, &room_id).await;
+
+        expire_session(&actor, 0, host_session_id).await;
+
+        let event = second_client.wait_for_event("host_changed").await;
+        assert_eq!(event["data"]["session_id"], session_id);
+
+        let state = actor.get_state_snapshot().await;
+        assert_eq!(state.rooms[&room_id].host_session_id, session_id);
+    }
+
+    #[tokio::test]
+    async fn expired_host_session_should_close_room_when_configured() {
+        let (_, actor) = start_actor_with_config(ServerConfig {
+            host_migration_policy: HostMigrationPolicy::CloseRoom,
+            ..ServerConfig::default()
+        })
+        .await;
+        let host = connect(&actor, 0).await;
+        let (room_id, host_session_id) = init_host(&actor, &host).await;
+        let client = connect(&actor, 1).await;
+        init_client(&actor, &client, &room_id).await;
+
+        expire_session(&actor, 0, host_session_id).await;
+
+        let event = client.wait_for_event("room_closed").await;
+        assert_eq!(event["data"]["reason"], "host_left");
+        let close = client
+            .wait_for(|message| message["method"] == "close")
+            .await;
+        assert_eq!(close["reason"], "room_closed");
+
+        let state = actor.get_state_snapshot().await;
+        assert!(state.rooms.is_empty());
+    }
+
+    /// Disconnects the client and expires its dangling session right away
+    /// instead of waiting for the timeout.
+    async fn expire_session(actor: &ActorRef<ServerActor>, client_id: u64, session_id: String) {
+        actor
+            .send_message(ServerMessage::Disconnect { client_id })
+            .unwrap();
+
+        for _ in 0..100 {
+            let state = actor.get_state_snapshot().await;
+
+            if state.dangling_sessions.contains_key(&session_id) {
+                break;
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        actor
+            .send_message(ServerMessage::RemoveDanglingSession { session_id })
+            .unwrap();
+    }
+
+    async fn start_actor() -> (ResponderDelegate, ActorRef<ServerActor>) {
+        start_actor_with_config(ServerConfig::default()).await
+    }
+
+    async fn start_actor_with_config(
+        config: ServerConfig,
+    ) -> (ResponderDelegate, ActorRef<ServerActor>) {
+        let mock_responder = ResponderDelegate::new();
+        let (actor, _) = Actor::spawn(None, ServerActor, config)
+            .await
+            .expect("failed to start server actor");
 
         (mock_responder, actor)
     }
 
+    async fn start_connection_actor(
+        server_actor: &ActorRef<ServerActor>,
+    ) -> ActorRef<ConnectionActor> {
+        let (connection_actor, _) = Actor::spawn(
+            None,
+            ConnectionActor,
+            (
+                server_actor.clone(),
+                Box::new(ResponderDelegate::new()),
+                ServerConfig::default(),
+            ),
+        )
+        .await
+        .expect("failed to start connection actor");
+
+        connection_actor
+    }
+
+    async fn create_room(actor: &ActorRef<ServerActor>) -> SessionState {
+        let connection_actor = start_connection_actor(actor).await;
+
+        call!(actor, |reply_port| ServerMessage::CreateRoom {
+            max_members: None,
+            password_hash: None,
+            connection_actor,
+            client_address: None,
+            profile: None,
+            reply_port
+        })
+        .unwrap()
+        .session_state
+    }
+
+    fn reading_state(line_index: u64) -> Value {
+        json!({
+            "game_id": "steins_gate",
+            "scene_id": "chapter_1",
+            "line_index": line_index,
+            "line_text": "El Psy Kongroo.",
+            "speaker": "Okabe",
+            "content_hash": "9f86d081884c7d65",
+        })
+    }
+
+    struct TestConnection {
+        client_id: u64,
+        sent_messages: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl TestConnection {
+        fn request(&self, actor: &ActorRef<ServerActor>, body: Value) -> String {
+            self.request_encoded(actor, body, Encoding::Json)
+        }
+
+        fn request_encoded(
+            &self,
+            actor: &ActorRef<ServerActor>,
+            body: Value,
+            encoding: Encoding,
+        ) -> String {
+            let id = nanoid!();
+
+            actor
+                .send_message(ServerMessage::Message {
+                    client_id: self.client_id,
+                    message: encoding.encode(&json!({"id": id, "body": body})),
+                })
+                .unwrap();
+
+            id
+        }
+
+        fn sent_messages(&self) -> Vec<Value> {
+            self.sent_messages
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|text| serde_json::from_str(text).unwrap())
+                .collect()
+        }
+
+        /// Waits until a message matching the predicate has been sent to this
+        /// connection and removes it, so that the same message isn't matched twice.
+        async fn wait_for(&self, predicate: impl Fn(&Value) -> bool) -> Value {
+            for _ in 0..100 {
+                let position = self.sent_messages().iter().position(&predicate);
+
+                if let Some(position) = position {
+                    let text = self.sent_messages.lock().unwrap().remove(position);
+                    return serde_json::from_str(&text).unwrap();
+                }
+
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+
+            panic!(
+                "no matching message was sent to client {}, got {:?}",
+                self.client_id,
+                self.sent_messages()
+            );
+        }
+
+        async fn wait_for_reply(&self, id: &str) -> Value {
+            self.wait_for(|message| message["method"] == "reply" && message["id"] == id)
+                .await
+        }
+
+        async fn wait_for_event(&self, event: &str) -> Value {
+            self.wait_for(|message| {
+                message["method"] == "event" && message["data"]["event"] == event
+            })
+            .await
+        }
+
+        async fn wait_for_error(&self, id: &str) -> Value {
+            self.wait_for(|message| message["method"] == "error" && message["id"] == id)
+                .await
+        }
+    }
+
+    async fn connect(actor: &ActorRef<ServerActor>, client_id: u64) -> TestConnection {
+        connect_from(actor, client_id, None).await
+    }
+
+    async fn connect_from(
+        actor: &ActorRef<ServerActor>,
+        client_id: u64,
+        client_address: Option<IpAddr>,
+    ) -> TestConnection {
+        let sent_messages = Arc::new(Mutex::new(Vec::new()));
+
+        actor
+            .send_message(ServerMessage::Connect {
+                client_id,
+                responder: Box::new(recording_responder(
+                    client_id,
+                    client_address,
+                    sent_messages.clone(),
+                )),
+            })
+            .unwrap();
+
+        TestConnection {
+            client_id,
+            sent_messages,
+        }
+    }
+
+    /// Returns the room id and the host's session id.
+    async fn init_host(
+        actor: &ActorRef<ServerActor>,
+        connection: &TestConnection,
+    ) -> (String, String) {
+        let id = connection.request(actor, json!({"method": "init", "init_type": "host"}));
+        let reply = connection.wait_for_reply(&id).await;
+
+        (
+            reply["data"]["room_id"].as_str().unwrap().into(),
+            reply["data"]["session_id"].as_str().unwrap().into(),
+        )
+    }
+
+    async fn init_client(
+        actor: &ActorRef<ServerActor>,
+        connection: &TestConnection,
+        room_id: &str,
+    ) -> String {
+        let id = connection.request(
+            actor,
+            json!({"method": "init", "init_type": "client", "room_id": room_id}),
+        );
+        let reply = connection.wait_for_reply(&id).await;
+
+        reply["data"]["session_id"].as_str().unwrap().into()
+    }
+
     #[async_trait]
     trait Snapshottable {
         async fn get_state_snapshot(&self) -> ServerStateSnapshot;
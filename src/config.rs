@@ -0,0 +1,71 @@
+use crate::ids::{IdGenerator, NanoidGenerator, RoomCodeGenerator};
+use std::{sync::Arc, time::Duration};
+
+/// What happens to a room once its host's dangling session expires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostMigrationPolicy {
+    /// Hands the host role to the client that has been in the room the longest,
+    /// preferring clients that are currently connected.
+    PromoteLongestConnected,
+    /// Closes the room and disconnects everyone in it.
+    CloseRoom,
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub host_migration_policy: HostMigrationPolicy,
+    /// How many past reading states each room keeps for `get_backlog`.
+    pub backlog_size: usize,
+    /// How many chat messages each room keeps for `get_chat_history`.
+    pub chat_history_size: usize,
+    /// How many recent events each room keeps to replay to reconnecting members.
+    pub event_replay_size: usize,
+    /// Maximum length of a chat message, in characters.
+    pub max_chat_message_length: usize,
+    /// Upper bound on the readers (host and clients) a room can hold, which
+    /// also caps the `max_members` a host asks for. Spectators don't count.
+    pub max_room_members: usize,
+    /// How often connections are pinged to check that the client is still there.
+    /// Only clients speaking protocol version 2 or later are pinged.
+    pub heartbeat_interval: Duration,
+    /// How many pings in a row may go unanswered before the client is treated
+    /// as disconnected.
+    pub max_missed_heartbeats: u32,
+    /// How many wrong passwords a room accepts from one address within
+    /// `password_attempt_window` before turning it away. Clients whose address
+    /// isn't known are counted per connection instead.
+    pub max_password_attempts: u32,
+    /// How many wrong passwords a room accepts from everyone together within
+    /// `password_attempt_window` before turning password checks away, which
+    /// bounds guessing spread over many addresses.
+    pub max_room_password_failures: u32,
+    /// How long wrong passwords count towards `max_password_attempts` and
+    /// `max_room_password_failures`, starting from the first one.
+    pub password_attempt_window: Duration,
+    /// Generates room ids, which are retried until they don't collide with a
+    /// live room.
+    pub room_id_generator: Arc<dyn IdGenerator>,
+    /// Generates session ids, which have to stay unguessable since holding one
+    /// is enough to reconnect to the session.
+    pub session_id_generator: Arc<dyn IdGenerator>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            host_migration_policy: HostMigrationPolicy::PromoteLongestConnected,
+            backlog_size: 500,
+            chat_history_size: 200,
+            event_replay_size: 500,
+            max_chat_message_length: 500,
+            max_room_members: 50,
+            heartbeat_interval: Duration::from_secs(15),
+            max_missed_heartbeats: 2,
+            max_password_attempts: 3,
+            max_room_password_failures: 30,
+            password_attempt_window: Duration::from_secs(5 * 60),
+            room_id_generator: Arc::new(RoomCodeGenerator::default()),
+            session_id_generator: Arc::new(NanoidGenerator),
+        }
+    }
+}
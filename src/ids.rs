@@ -0,0 +1,95 @@
+use nanoid::nanoid;
+use std::fmt::Debug;
+
+/// Letters and digits that can't be mistaken for one another when read out
+/// loud or copied by hand, so no `0`/`O`, `1`/`I`/`L` or `5`/`S`.
+const ROOM_CODE_ALPHABET: [char; 29] = [
+    '2', '3', '4', '6', '7', '8', '9', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'J', 'K', 'M', 'N',
+    'P', 'Q', 'R', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z',
+];
+
+/// Room codes are typed in by hand, so they're matched regardless of case and
+/// surrounding whitespace.
+pub fn normalize_room_code(room_code: &str) -> String {
+    room_code.trim().to_uppercase()
+}
+
+/// Source of the ids handed out by the server. Uniqueness is up to the caller,
+/// so generators are free to return the same id twice.
+pub trait IdGenerator: Debug + Send + Sync {
+    fn generate(&self) -> String;
+}
+
+/// Short codes meant to be shared by voice, used for room ids.
+#[derive(Debug, Clone, Copy)]
+pub struct RoomCodeGenerator {
+    pub length: usize,
+}
+
+impl Default for RoomCodeGenerator {
+    fn default() -> Self {
+        RoomCodeGenerator { length: 6 }
+    }
+}
+
+impl IdGenerator for RoomCodeGenerator {
+    fn generate(&self) -> String {
+        let length = self.length;
+        nanoid!(length, &ROOM_CODE_ALPHABET)
+    }
+}
+
+/// Long, unguessable ids, used for session ids.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NanoidGenerator;
+
+impl IdGenerator for NanoidGenerator {
+    fn generate(&self) -> String {
+        nanoid!()
+    }
+}
+
+/// Hands out the given ids in order, panicking once they run out.
+#[cfg(test)]
+#[derive(Debug)]
+pub struct ScriptedIdGenerator {
+    ids: std::sync::Mutex<std::collections::VecDeque<String>>,
+}
+
+#[cfg(test)]
+impl ScriptedIdGenerator {
+    pub fn new(ids: &[&str]) -> Self {
+        ScriptedIdGenerator {
+            ids: std::sync::Mutex::new(ids.iter().map(|id| id.to_string()).collect()),
+        }
+    }
+}
+
+#[cfg(test)]
+impl IdGenerator for ScriptedIdGenerator {
+    fn generate(&self) -> String {
+        self.ids
+            .lock()
+            .unwrap()
+            .pop_front()
+            .expect("ran out of scripted ids")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn room_code_should_use_unambiguous_alphabet() {
+        let code = RoomCodeGenerator::default().generate();
+
+        assert_eq!(code.len(), 6);
+        assert!(code.chars().all(|char| ROOM_CODE_ALPHABET.contains(&char)));
+    }
+
+    #[test]
+    fn session_id_should_stay_long() {
+        assert_eq!(NanoidGenerator.generate().len(), 21);
+    }
+}
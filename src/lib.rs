@@ -1,19 +1,28 @@
 use crate::actors::server_actor::{ServerActor, ServerMessage};
 use dyn_clone::DynClone;
 use ractor::Actor;
-use simple_websockets::{Event, Message as WebSocketMessage, Responder};
-use std::fmt::Debug;
+use std::{fmt::Debug, net::IpAddr};
+use websocket::{Event, Message as WebSocketMessage, Responder};
 
 #[cfg(test)]
 use mockall::mock;
 
 mod actors;
+mod config;
+mod ids;
 mod messages;
+mod room;
+mod websocket;
+
+pub use config::{HostMigrationPolicy, ServerConfig};
+pub use ids::{IdGenerator, NanoidGenerator, RoomCodeGenerator};
 
 pub trait ResponderTrait: Send + Debug + DynClone {
     fn send(&self, message: WebSocketMessage) -> bool;
     fn close(&self);
     fn client_id(&self) -> u64;
+    /// Address the client connected from, if known.
+    fn client_address(&self) -> Option<IpAddr>;
 }
 
 #[derive(Debug, Clone)]
@@ -33,6 +42,10 @@ impl ResponderTrait for ResponderDelegate {
     fn client_id(&self) -> u64 {
         self.responder.client_id()
     }
+
+    fn client_address(&self) -> Option<IpAddr> {
+        Some(self.responder.address().ip())
+    }
 }
 
 #[cfg(test)]
@@ -46,13 +59,50 @@ mock! {
         fn send(&self, message: WebSocketMessage) -> bool;
         fn close(&self);
         fn client_id(&self) -> u64;
+        fn client_address(&self) -> Option<IpAddr>;
     }
 }
 
-pub async fn launch(port: u16) {
-    let event_hub =
-        simple_websockets::launch(port).expect(&format!("failed to launch on port {}", port));
-    let (actor, _) = Actor::spawn(None, ServerActor, ())
+/// Builds a mock responder that records every frame sent through it (and
+/// through its clones) into `sent_messages`. Binary frames are decoded from
+/// MessagePack and recorded as JSON wrapped in `{"binary": ...}`.
+#[cfg(test)]
+pub fn recording_responder(
+    client_id: u64,
+    client_address: Option<IpAddr>,
+    sent_messages: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+) -> MockResponderDelegate {
+    let mut responder = MockResponderDelegate::new();
+
+    let send_messages = sent_messages.clone();
+    responder.expect_send().returning(move |message| {
+        let text = match message {
+            WebSocketMessage::Text(text) => text,
+            WebSocketMessage::Binary(bytes) => {
+                let value: serde_json::Value = rmp_serde::from_slice(&bytes).unwrap();
+                serde_json::json!({ "binary": value }).to_string()
+            }
+        };
+        send_messages.lock().unwrap().push(text);
+        true
+    });
+    responder.expect_close().return_const(());
+    responder.expect_client_id().return_const(client_id);
+    responder
+        .expect_client_address()
+        .return_const(client_address);
+    responder
+        .expect_clone()
+        .returning(move || recording_responder(client_id, client_address, sent_messages.clone()));
+
+    responder
+}
+
+pub async fn launch(port: u16, config: ServerConfig) {
+    let mut event_hub = websocket::launch(port)
+        .await
+        .unwrap_or_else(|_| panic!("failed to launch on port {}", port));
+    let (actor, _) = Actor::spawn(None, ServerActor, config)
         .await
         .expect("failed to start server actor");
 
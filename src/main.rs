@@ -1,6 +1,6 @@
-use vnsync_server::launch;
+use vnsync_server::{launch, ServerConfig};
 
 #[tokio::main]
 async fn main() {
-    launch(8080).await;
+    launch(8080, ServerConfig::default()).await;
 }
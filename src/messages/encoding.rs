@@ -0,0 +1,73 @@
+use crate::websocket::Message as WebSocketMessage;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// How outbound messages are serialized for a connection. Inbound messages are
+/// decoded based on the frame type instead, so clients can switch at any time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// Sent as text frames.
+    #[default]
+    Json,
+    /// Sent as binary frames.
+    MessagePack,
+}
+
+impl Encoding {
+    /// Text frames are read as JSON and binary frames as MessagePack.
+    pub fn decode<T: DeserializeOwned>(message: &WebSocketMessage) -> Result<T, String> {
+        match message {
+            WebSocketMessage::Text(text) => {
+                serde_json::from_str(text).map_err(|error| error.to_string())
+            }
+            WebSocketMessage::Binary(bytes) => {
+                rmp_serde::from_slice(bytes).map_err(|error| error.to_string())
+            }
+        }
+    }
+
+    pub fn encode(&self, value: &impl Serialize) -> WebSocketMessage {
+        match self {
+            Encoding::Json => WebSocketMessage::Text(
+                serde_json::to_string(value).expect("should serialize to JSON"),
+            ),
+            // field names have to be kept, since messages are tagged by them
+            Encoding::MessagePack => WebSocketMessage::Binary(
+                rmp_serde::to_vec_named(value).expect("should serialize to MessagePack"),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{
+        inbound::{InboundMessage, MessageBody},
+        outbound::OutboundMessage,
+    };
+    use serde_json::{json, Value};
+
+    #[test]
+    fn binary_frames_should_be_decoded_as_message_pack() {
+        let body = json!({"id": "1", "body": {"method": "send_chat", "text": "Tuturu"}});
+        let frame = Encoding::MessagePack.encode(&body);
+
+        let message: InboundMessage = Encoding::decode(&frame).unwrap();
+
+        assert!(matches!(message.body, MessageBody::SendChat { text } if text == "Tuturu"));
+    }
+
+    #[test]
+    fn message_pack_should_keep_tagged_fields() {
+        let frame = Encoding::MessagePack.encode(&OutboundMessage::Close {
+            reason: "kicked".into(),
+        });
+
+        let WebSocketMessage::Binary(bytes) = frame else {
+            panic!("should be a binary frame");
+        };
+        let value: Value = rmp_serde::from_slice(&bytes).unwrap();
+
+        assert_eq!(value, json!({"method": "close", "reason": "kicked"}));
+    }
+}
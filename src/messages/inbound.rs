@@ -1,25 +1,135 @@
+use super::{
+    profile::Profile,
+    protocol::Handshake,
+    state::{ReadingProgress, ReadingState},
+};
+use crate::room::Role;
 use serde::Deserialize;
 
 #[derive(Debug, Deserialize)]
 #[serde(tag = "init_type")]
 pub enum InitMessage {
     #[serde(rename = "host")]
-    Host,
+    Host {
+        max_members: Option<usize>,
+        password: Option<String>,
+        profile: Option<Profile>,
+    },
     #[serde(rename = "client")]
-    Client { room_id: String },
+    Client {
+        room_id: String,
+        password: Option<String>,
+        profile: Option<Profile>,
+    },
     #[serde(rename = "reconnect")]
-    Reconnect { session_id: String },
+    Reconnect {
+        session_id: String,
+        /// Sequence number of the last event the client got. Missed events
+        /// aren't replayed when unset.
+        last_seen_seq: Option<u64>,
+    },
+    #[serde(rename = "spectator")]
+    Spectator {
+        room_id: String,
+        token: String,
+        profile: Option<Profile>,
+    },
+    #[serde(rename = "invite")]
+    Invite {
+        token: String,
+        profile: Option<Profile>,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InitRequest {
+    #[serde(flatten)]
+    pub handshake: Handshake,
+    #[serde(flatten)]
+    pub init_type: InitMessage,
+}
+
+impl InitMessage {
+    /// The profile the member wants to join with. Reconnecting members keep
+    /// the profile of their session instead.
+    pub fn profile(&self) -> Option<&Profile> {
+        match self {
+            InitMessage::Host { profile, .. }
+            | InitMessage::Client { profile, .. }
+            | InitMessage::Spectator { profile, .. }
+            | InitMessage::Invite { profile, .. } => profile.as_ref(),
+            InitMessage::Reconnect { .. } => None,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(tag = "method")]
 pub enum MessageBody {
     #[serde(rename = "init")]
-    Init(InitMessage),
+    Init(InitRequest),
+    /// Answers a server ping. Unlike other requests, it gets no reply.
+    #[serde(rename = "pong")]
+    Pong { ping_id: u64 },
     #[serde(rename = "get_state_string")]
     GetStateString,
     #[serde(rename = "set_state_string")]
     SetStateString { string: String },
+    #[serde(rename = "get_state")]
+    GetState,
+    #[serde(rename = "set_state")]
+    SetState { state: ReadingState },
+    #[serde(rename = "get_backlog")]
+    GetBacklog { before: Option<u64>, limit: usize },
+    #[serde(rename = "send_chat")]
+    SendChat { text: String },
+    #[serde(rename = "get_chat_history")]
+    GetChatHistory { before: Option<u64>, limit: usize },
+    #[serde(rename = "open_poll")]
+    OpenPoll {
+        options: Vec<String>,
+        duration_ms: Option<u64>,
+    },
+    #[serde(rename = "vote")]
+    Vote {
+        poll_id: String,
+        option_index: usize,
+    },
+    #[serde(rename = "close_poll")]
+    ClosePoll { poll_id: String },
+    #[serde(rename = "set_readiness_barrier")]
+    SetReadinessBarrier {
+        enabled: bool,
+        ignore_dangling: bool,
+    },
+    #[serde(rename = "set_ready")]
+    SetReady { ready: bool },
+    #[serde(rename = "queue_state")]
+    QueueState { state: ReadingState },
+    #[serde(rename = "report_progress")]
+    ReportProgress { progress: ReadingProgress },
+    #[serde(rename = "set_profile")]
+    SetProfile { profile: Profile },
+    #[serde(rename = "list_members")]
+    ListMembers,
+    #[serde(rename = "transfer_host")]
+    TransferHost { session_id: String },
+    #[serde(rename = "kick")]
+    Kick { session_id: String },
+    #[serde(rename = "ban")]
+    Ban { session_id: String },
+    #[serde(rename = "create_invite")]
+    CreateInvite {
+        role: Role,
+        /// Unlimited when unset.
+        max_uses: Option<u32>,
+        expires_in_ms: u64,
+    },
+    #[serde(rename = "revoke_invite")]
+    RevokeInvite { token: String },
+    /// Ends the session, which can't be reconnected to afterwards.
+    #[serde(rename = "leave")]
+    Leave,
 }
 
 #[derive(Debug, Deserialize)]
@@ -27,3 +137,10 @@ pub struct InboundMessage {
     pub id: String,
     pub body: MessageBody,
 }
+
+/// Used to recover the request id from messages whose body couldn't be parsed,
+/// so that the error can be reported back as a reply to that request.
+#[derive(Debug, Deserialize)]
+pub struct InboundMessageId {
+    pub id: String,
+}
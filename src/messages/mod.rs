@@ -1,2 +1,6 @@
+pub mod encoding;
 pub mod inbound;
 pub mod outbound;
+pub mod profile;
+pub mod protocol;
+pub mod state;
@@ -1,27 +1,281 @@
-use crate::{ResponderDelegate, ResponderTrait};
+use super::{
+    encoding::Encoding,
+    profile::Profile,
+    protocol::Protocol,
+    state::{ReadingProgress, ReadingState},
+};
+use crate::{room::Role, ResponderTrait};
 use serde::Serialize;
-use simple_websockets::Message as WebSocketMessage;
 
 #[derive(Debug, Serialize)]
 #[serde(tag = "init_type")]
 pub enum InitType {
     #[serde(rename = "host")]
-    Host { session_id: String, room_id: String },
+    Host {
+        session_id: String,
+        room_id: String,
+        spectator_token: String,
+        max_members: usize,
+    },
     #[serde(rename = "client")]
-    Client { session_id: String },
+    Client {
+        session_id: String,
+        host_session_id: String,
+    },
+    #[serde(rename = "spectator")]
+    Spectator {
+        session_id: String,
+        host_session_id: String,
+    },
     #[serde(rename = "reconnect")]
-    Reconnect,
+    Reconnect {
+        /// Set when events the session missed are no longer kept, so the
+        /// client has to fetch the room's state from scratch. Otherwise the
+        /// missed events follow this reply.
+        resync_required: bool,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MemberInfo {
+    pub session_id: String,
+    pub role: Role,
+    pub profile: Option<Profile>,
+    pub connected: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryEntry<T> {
+    pub index: u64,
+    #[serde(flatten)]
+    pub item: T,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BacklogItem {
+    State {
+        state: ReadingState,
+    },
+    PollResult {
+        poll_id: String,
+        options: Vec<String>,
+        tallies: Vec<u32>,
+        winner: Option<usize>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MemberProgress {
+    pub session_id: String,
+    pub progress: Option<ReadingProgress>,
+    /// How many lines the member is behind the room's state, if both are known.
+    pub lines_behind: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatMessage {
+    pub session_id: String,
+    /// The sender's profile at the time the message was sent.
+    pub profile: Option<Profile>,
+    pub text: String,
+    /// Milliseconds since the Unix epoch, as seen by the server.
+    pub timestamp: u64,
 }
 
 #[derive(Debug, Serialize)]
 #[serde(tag = "reply_to")]
 pub enum ReplyData {
     #[serde(rename = "init")]
-    Init(InitType),
+    Init {
+        #[serde(flatten)]
+        init_type: InitType,
+        #[serde(flatten)]
+        protocol: Protocol,
+    },
     #[serde(rename = "get_state_string")]
     GetStateString { string: String },
     #[serde(rename = "set_state_string")]
     SetStateString,
+    #[serde(rename = "get_state")]
+    GetState { state: Option<ReadingState> },
+    #[serde(rename = "set_state")]
+    SetState,
+    #[serde(rename = "get_backlog")]
+    GetBacklog {
+        entries: Vec<HistoryEntry<BacklogItem>>,
+        has_more: bool,
+    },
+    #[serde(rename = "send_chat")]
+    SendChat,
+    #[serde(rename = "get_chat_history")]
+    GetChatHistory {
+        entries: Vec<HistoryEntry<ChatMessage>>,
+        has_more: bool,
+    },
+    #[serde(rename = "open_poll")]
+    OpenPoll { poll_id: String },
+    #[serde(rename = "vote")]
+    Vote,
+    #[serde(rename = "close_poll")]
+    ClosePoll,
+    #[serde(rename = "set_readiness_barrier")]
+    SetReadinessBarrier,
+    #[serde(rename = "set_ready")]
+    SetReady,
+    #[serde(rename = "queue_state")]
+    QueueState,
+    #[serde(rename = "report_progress")]
+    ReportProgress,
+    #[serde(rename = "set_profile")]
+    SetProfile,
+    #[serde(rename = "list_members")]
+    ListMembers { members: Vec<MemberInfo> },
+    #[serde(rename = "transfer_host")]
+    TransferHost,
+    #[serde(rename = "kick")]
+    Kick,
+    #[serde(rename = "ban")]
+    Ban,
+    #[serde(rename = "create_invite")]
+    CreateInvite {
+        token: String,
+        /// Milliseconds since the Unix epoch.
+        expires_at: u64,
+    },
+    #[serde(rename = "revoke_invite")]
+    RevokeInvite,
+    #[serde(rename = "leave")]
+    Leave,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    InvalidRequest,
+    InvalidState,
+    InvalidProgress,
+    InvalidChatMessage,
+    InvalidPoll,
+    PollAlreadyOpen,
+    PollNotFound,
+    InvalidVote,
+    ReadinessBarrierDisabled,
+    InvalidPassword,
+    InvalidProfile,
+    DisplayNameTaken,
+    InvalidInvite,
+    InviteNotFound,
+    NotInitialized,
+    AlreadyInitialized,
+    PermissionDenied,
+    MemberNotFound,
+    RoomNotFound,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemberLeftReason {
+    Left,
+    SessionExpired,
+    Kicked,
+    Banned,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomClosedReason {
+    HostLeft,
+}
+
+impl ErrorCode {
+    pub fn description(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidRequest => "the request couldn't be parsed",
+            ErrorCode::InvalidState => "the reading state is invalid",
+            ErrorCode::InvalidProgress => "the reported progress is invalid",
+            ErrorCode::InvalidChatMessage => "the chat message is invalid",
+            ErrorCode::InvalidPoll => "the poll is invalid",
+            ErrorCode::PollAlreadyOpen => "another poll is already open in the room",
+            ErrorCode::PollNotFound => "no such poll is open in the room",
+            ErrorCode::InvalidVote => "no such option exists in the poll",
+            ErrorCode::ReadinessBarrierDisabled => "the readiness barrier isn't enabled",
+            ErrorCode::InvalidPassword => "the room password is incorrect",
+            ErrorCode::InvalidProfile => "the profile is invalid",
+            ErrorCode::DisplayNameTaken => "another member of the room uses that display name",
+            ErrorCode::InvalidInvite => "the invite is invalid",
+            ErrorCode::InviteNotFound => "no such invite exists in the room",
+            ErrorCode::NotInitialized => "the connection has to be initialized first",
+            ErrorCode::AlreadyInitialized => "the connection is already initialized",
+            ErrorCode::PermissionDenied => "the session's role doesn't allow this request",
+            ErrorCode::MemberNotFound => "no such member exists in the room",
+            ErrorCode::RoomNotFound => "the room no longer exists",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event")]
+pub enum EventData {
+    #[serde(rename = "state_string_changed")]
+    StateStringChanged { string: String },
+    #[serde(rename = "state_changed")]
+    StateChanged { state: ReadingState },
+    #[serde(rename = "chat_message")]
+    ChatMessage(HistoryEntry<ChatMessage>),
+    #[serde(rename = "poll_opened")]
+    PollOpened {
+        poll_id: String,
+        options: Vec<String>,
+        /// Milliseconds since the Unix epoch at which the poll closes on its own.
+        deadline: Option<u64>,
+    },
+    #[serde(rename = "poll_updated")]
+    PollUpdated { poll_id: String, tallies: Vec<u32> },
+    #[serde(rename = "poll_closed")]
+    PollClosed {
+        poll_id: String,
+        tallies: Vec<u32>,
+        winner: Option<usize>,
+    },
+    #[serde(rename = "all_ready")]
+    AllReady,
+    #[serde(rename = "progress_updated")]
+    ProgressUpdated { members: Vec<MemberProgress> },
+    #[serde(rename = "member_joined")]
+    MemberJoined {
+        session_id: String,
+        role: Role,
+        profile: Option<Profile>,
+    },
+    #[serde(rename = "profile_changed")]
+    ProfileChanged {
+        session_id: String,
+        profile: Profile,
+    },
+    #[serde(rename = "member_left")]
+    MemberLeft {
+        session_id: String,
+        profile: Option<Profile>,
+        reason: MemberLeftReason,
+    },
+    #[serde(rename = "member_disconnected")]
+    MemberDisconnected {
+        session_id: String,
+        profile: Option<Profile>,
+    },
+    #[serde(rename = "member_reconnected")]
+    MemberReconnected {
+        session_id: String,
+        profile: Option<Profile>,
+    },
+    /// Sent to hosts when the old token stops working, e.g. after a ban.
+    #[serde(rename = "spectator_token_changed")]
+    SpectatorTokenChanged { spectator_token: String },
+    #[serde(rename = "host_changed")]
+    HostChanged { session_id: String },
+    #[serde(rename = "room_closed")]
+    RoomClosed { reason: RoomClosedReason },
 }
 
 #[derive(Debug, Serialize)]
@@ -31,11 +285,30 @@ pub enum OutboundMessage {
     Close { reason: String },
     #[serde(rename = "reply")]
     Reply { id: String, data: ReplyData },
+    #[serde(rename = "event")]
+    Event {
+        /// Numbered separately for each member and consecutive from 1, so a
+        /// gap means an event was lost. Used to catch up after reconnecting.
+        seq: u64,
+        data: EventData,
+    },
+    /// Has to be answered with a `pong` request carrying the same `ping_id`.
+    #[serde(rename = "ping")]
+    Ping {
+        ping_id: u64,
+        /// Round-trip time measured with the previous ping, if it was answered.
+        round_trip_ms: Option<u64>,
+    },
+    #[serde(rename = "error")]
+    Error {
+        id: String,
+        code: ErrorCode,
+        message: String,
+    },
 }
 
 impl OutboundMessage {
-    pub fn send(&self, responder: &Box<dyn ResponderTrait>) {
-        let message_json = serde_json::to_string(self).expect("should serialize OutboundMessage");
-        responder.send(WebSocketMessage::Text(message_json));
+    pub fn send(&self, responder: &dyn ResponderTrait, encoding: Encoding) {
+        responder.send(encoding.encode(self));
     }
 }
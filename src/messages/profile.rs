@@ -0,0 +1,143 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+
+const MAX_DISPLAY_NAME_LENGTH: usize = 32;
+const MAX_AVATAR_ID_LENGTH: usize = 64;
+
+/// How a member presents themselves to the rest of the room.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Profile {
+    pub display_name: String,
+    /// Hex color in the `#rrggbb` form.
+    pub color: Option<String>,
+    pub avatar_id: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ProfileError {
+    EmptyDisplayName,
+    DisplayNameTooLong,
+    InvalidDisplayName,
+    InvalidColor,
+    InvalidAvatarId,
+}
+
+impl Profile {
+    pub fn validate(&self) -> Result<(), ProfileError> {
+        if self.display_name.trim().is_empty() {
+            return Err(ProfileError::EmptyDisplayName);
+        }
+
+        if self.display_name.chars().count() > MAX_DISPLAY_NAME_LENGTH {
+            return Err(ProfileError::DisplayNameTooLong);
+        }
+
+        // surrounding whitespace would make otherwise equal names look unique
+        if self.display_name.trim() != self.display_name
+            || self.display_name.chars().any(char::is_control)
+        {
+            return Err(ProfileError::InvalidDisplayName);
+        }
+
+        if let Some(color) = &self.color {
+            let is_hex_color = color.len() == 7
+                && color.starts_with('#')
+                && color[1..].chars().all(|char| char.is_ascii_hexdigit());
+
+            if !is_hex_color {
+                return Err(ProfileError::InvalidColor);
+            }
+        }
+
+        if let Some(avatar_id) = &self.avatar_id {
+            let is_valid = !avatar_id.is_empty()
+                && avatar_id.len() <= MAX_AVATAR_ID_LENGTH
+                && avatar_id
+                    .chars()
+                    .all(|char| char.is_ascii_alphanumeric() || char == '-' || char == '_');
+
+            if !is_valid {
+                return Err(ProfileError::InvalidAvatarId);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether both profiles would show up under the same name.
+    pub fn has_same_display_name(&self, other: &Profile) -> bool {
+        self.display_name.to_lowercase() == other.display_name.to_lowercase()
+    }
+}
+
+impl Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileError::EmptyDisplayName => f.write_str("display_name must not be empty"),
+            ProfileError::DisplayNameTooLong => write!(
+                f,
+                "display_name must be at most {} characters long",
+                MAX_DISPLAY_NAME_LENGTH
+            ),
+            ProfileError::InvalidDisplayName => f.write_str(
+                "display_name must not contain control characters or surrounding whitespace",
+            ),
+            ProfileError::InvalidColor => f.write_str("color must be in the #rrggbb form"),
+            ProfileError::InvalidAvatarId => write!(
+                f,
+                "avatar_id must be 1 to {} letters, digits, dashes or underscores",
+                MAX_AVATAR_ID_LENGTH
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(display_name: &str) -> Profile {
+        Profile {
+            display_name: display_name.into(),
+            color: Some("#ff8800".into()),
+            avatar_id: Some("kurisu_01".into()),
+        }
+    }
+
+    #[test]
+    fn validate_should_accept_valid_profile() {
+        assert_eq!(profile("Okabe").validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate_should_reject_bad_display_names() {
+        assert_eq!(
+            profile("   ").validate(),
+            Err(ProfileError::EmptyDisplayName)
+        );
+        assert_eq!(
+            profile(&"a".repeat(33)).validate(),
+            Err(ProfileError::DisplayNameTooLong)
+        );
+        assert_eq!(
+            profile(" Okabe").validate(),
+            Err(ProfileError::InvalidDisplayName)
+        );
+    }
+
+    #[test]
+    fn validate_should_reject_bad_color() {
+        let profile = Profile {
+            color: Some("orange".into()),
+            ..profile("Okabe")
+        };
+
+        assert_eq!(profile.validate(), Err(ProfileError::InvalidColor));
+    }
+
+    #[test]
+    fn display_names_should_be_compared_case_insensitively() {
+        assert!(profile("Okabe").has_same_display_name(&profile("OKABE")));
+        assert!(!profile("Okabe").has_same_display_name(&profile("Mayuri")));
+    }
+}
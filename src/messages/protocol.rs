@@ -0,0 +1,117 @@
+use super::encoding::Encoding;
+use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+
+/// Version spoken by clients that predate version negotiation.
+pub const LEGACY_PROTOCOL_VERSION: u32 = 1;
+
+/// Versions served side by side. Version 2 adds `ping`/`pong` heartbeats,
+/// which older clients wouldn't answer.
+pub const SUPPORTED_PROTOCOL_VERSIONS: RangeInclusive<u32> = 1..=2;
+
+/// Optional features a client can opt into, independently of the version.
+pub const SUPPORTED_CAPABILITIES: &[&str] = &[MESSAGE_PACK_CAPABILITY];
+
+/// Has the server reply in MessagePack binary frames instead of JSON.
+pub const MESSAGE_PACK_CAPABILITY: &str = "msgpack";
+
+/// What the client offers during init.
+#[derive(Debug, Deserialize)]
+pub struct Handshake {
+    #[serde(default = "legacy_protocol_versions")]
+    pub protocol_versions: Vec<u32>,
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+}
+
+/// What the connection ends up speaking.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Protocol {
+    pub protocol_version: u32,
+    pub capabilities: Vec<String>,
+}
+
+fn legacy_protocol_versions() -> Vec<u32> {
+    vec![LEGACY_PROTOCOL_VERSION]
+}
+
+impl Handshake {
+    /// Picks the highest version both sides speak, keeping only the
+    /// capabilities the server knows. `None` if there's no such version.
+    pub fn negotiate(&self) -> Option<Protocol> {
+        let protocol_version = self
+            .protocol_versions
+            .iter()
+            .copied()
+            .filter(|version| SUPPORTED_PROTOCOL_VERSIONS.contains(version))
+            .max()?;
+
+        let capabilities = SUPPORTED_CAPABILITIES
+            .iter()
+            .filter(|capability| {
+                self.capabilities
+                    .iter()
+                    .any(|offered| offered == *capability)
+            })
+            .map(|capability| capability.to_string())
+            .collect();
+
+        Some(Protocol {
+            protocol_version,
+            capabilities,
+        })
+    }
+}
+
+impl Protocol {
+    /// Legacy clients aren't pinged, so a dead one is only noticed once its
+    /// socket closes.
+    pub fn has_heartbeat(&self) -> bool {
+        self.protocol_version >= 2
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        if self
+            .capabilities
+            .iter()
+            .any(|capability| capability == MESSAGE_PACK_CAPABILITY)
+        {
+            Encoding::MessagePack
+        } else {
+            Encoding::Json
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handshake(protocol_versions: &[u32]) -> Handshake {
+        Handshake {
+            protocol_versions: protocol_versions.to_vec(),
+            capabilities: vec!["unknown".into()],
+        }
+    }
+
+    #[test]
+    fn negotiate_should_pick_highest_common_version() {
+        let protocol = handshake(&[1, 2, 7]).negotiate().unwrap();
+
+        assert_eq!(protocol.protocol_version, 2);
+        assert!(protocol.capabilities.is_empty());
+    }
+
+    #[test]
+    fn negotiate_should_fail_without_common_version() {
+        assert_eq!(handshake(&[7]).negotiate(), None);
+        assert_eq!(handshake(&[]).negotiate(), None);
+    }
+
+    #[test]
+    fn missing_versions_should_default_to_legacy_version() {
+        let handshake: Handshake = serde_json::from_str("{}").unwrap();
+
+        assert_eq!(handshake.negotiate().unwrap().protocol_version, 1);
+    }
+}
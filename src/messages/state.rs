@@ -0,0 +1,188 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+
+const MAX_ID_LENGTH: usize = 128;
+const MAX_LINE_TEXT_LENGTH: usize = 4096;
+const MAX_SPEAKER_LENGTH: usize = 128;
+const MAX_CONTENT_HASH_LENGTH: usize = 128;
+
+/// Position of the reader in a visual novel, shared with everyone in the room.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReadingState {
+    pub game_id: String,
+    pub scene_id: String,
+    pub line_index: u64,
+    pub line_text: String,
+    pub speaker: Option<String>,
+    pub content_hash: String,
+}
+
+/// Position a reader reports for themselves, which may lag behind the room's
+/// [`ReadingState`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReadingProgress {
+    pub line_index: u64,
+    pub content_hash: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReadingStateError {
+    EmptyField(&'static str),
+    FieldTooLong {
+        field: &'static str,
+        max_length: usize,
+    },
+    InvalidContentHash,
+}
+
+impl ReadingState {
+    pub fn validate(&self) -> Result<(), ReadingStateError> {
+        for (field, value, max_length) in [
+            ("game_id", &self.game_id, MAX_ID_LENGTH),
+            ("scene_id", &self.scene_id, MAX_ID_LENGTH),
+            ("content_hash", &self.content_hash, MAX_CONTENT_HASH_LENGTH),
+        ] {
+            if value.is_empty() {
+                return Err(ReadingStateError::EmptyField(field));
+            }
+
+            if value.chars().count() > max_length {
+                return Err(ReadingStateError::FieldTooLong { field, max_length });
+            }
+        }
+
+        if self.line_text.chars().count() > MAX_LINE_TEXT_LENGTH {
+            return Err(ReadingStateError::FieldTooLong {
+                field: "line_text",
+                max_length: MAX_LINE_TEXT_LENGTH,
+            });
+        }
+
+        if let Some(speaker) = &self.speaker {
+            if speaker.chars().count() > MAX_SPEAKER_LENGTH {
+                return Err(ReadingStateError::FieldTooLong {
+                    field: "speaker",
+                    max_length: MAX_SPEAKER_LENGTH,
+                });
+            }
+        }
+
+        validate_content_hash(&self.content_hash)
+    }
+}
+
+impl ReadingProgress {
+    pub fn validate(&self) -> Result<(), ReadingStateError> {
+        if self.content_hash.is_empty() {
+            return Err(ReadingStateError::EmptyField("content_hash"));
+        }
+
+        if self.content_hash.chars().count() > MAX_CONTENT_HASH_LENGTH {
+            return Err(ReadingStateError::FieldTooLong {
+                field: "content_hash",
+                max_length: MAX_CONTENT_HASH_LENGTH,
+            });
+        }
+
+        validate_content_hash(&self.content_hash)
+    }
+}
+
+fn validate_content_hash(content_hash: &str) -> Result<(), ReadingStateError> {
+    if !content_hash.chars().all(|char| char.is_ascii_hexdigit()) {
+        return Err(ReadingStateError::InvalidContentHash);
+    }
+
+    Ok(())
+}
+
+impl Display for ReadingStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadingStateError::EmptyField(field) => write!(f, "{} must not be empty", field),
+            ReadingStateError::FieldTooLong { field, max_length } => {
+                write!(
+                    f,
+                    "{} must be at most {} characters long",
+                    field, max_length
+                )
+            }
+            ReadingStateError::InvalidContentHash => {
+                f.write_str("content_hash must be a hexadecimal string")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading_state() -> ReadingState {
+        ReadingState {
+            game_id: "steins_gate".into(),
+            scene_id: "chapter_1".into(),
+            line_index: 42,
+            line_text: "El Psy Kongroo.".into(),
+            speaker: Some("Okabe".into()),
+            content_hash: "9f86d081884c7d65".into(),
+        }
+    }
+
+    #[test]
+    fn validate_should_accept_valid_state() {
+        assert_eq!(reading_state().validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate_should_reject_empty_ids() {
+        let state = ReadingState {
+            scene_id: "".into(),
+            ..reading_state()
+        };
+
+        assert_eq!(
+            state.validate(),
+            Err(ReadingStateError::EmptyField("scene_id"))
+        );
+    }
+
+    #[test]
+    fn validate_should_reject_overly_long_line_text() {
+        let state = ReadingState {
+            line_text: "a".repeat(MAX_LINE_TEXT_LENGTH + 1),
+            ..reading_state()
+        };
+
+        assert_eq!(
+            state.validate(),
+            Err(ReadingStateError::FieldTooLong {
+                field: "line_text",
+                max_length: MAX_LINE_TEXT_LENGTH
+            })
+        );
+    }
+
+    #[test]
+    fn progress_validate_should_reject_empty_content_hash() {
+        let progress = ReadingProgress {
+            line_index: 1,
+            content_hash: "".into(),
+        };
+
+        assert_eq!(
+            progress.validate(),
+            Err(ReadingStateError::EmptyField("content_hash"))
+        );
+    }
+
+    #[test]
+    fn validate_should_reject_non_hex_content_hash() {
+        let state = ReadingState {
+            content_hash: "not a hash".into(),
+            ..reading_state()
+        };
+
+        assert_eq!(state.validate(), Err(ReadingStateError::InvalidContentHash));
+    }
+}
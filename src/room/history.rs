@@ -0,0 +1,118 @@
+use crate::messages::outbound::HistoryEntry;
+use std::collections::VecDeque;
+
+/// Upper bound on how many entries a single page request can return.
+pub const MAX_PAGE_SIZE: usize = 100;
+
+/// Bounded, append-only log whose entries get consecutive indices. Once the
+/// capacity is reached the oldest entries are dropped.
+#[derive(Debug, Clone)]
+pub struct History<T> {
+    entries: VecDeque<HistoryEntry<T>>,
+    capacity: usize,
+    next_index: u64,
+}
+
+impl<T: Clone> History<T> {
+    pub fn new(capacity: usize) -> Self {
+        History {
+            entries: VecDeque::new(),
+            capacity,
+            next_index: 0,
+        }
+    }
+
+    pub fn push(&mut self, item: T) -> u64 {
+        let index = self.next_index;
+        self.next_index += 1;
+
+        if self.capacity == 0 {
+            return index;
+        }
+
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back(HistoryEntry { index, item });
+        index
+    }
+
+    /// Returns up to `limit` of the newest entries with an index lower than
+    /// `before` (or of all entries when it's `None`), oldest first, along with
+    /// whether any older entries are left to page through.
+    pub fn page(&self, before: Option<u64>, limit: usize) -> (Vec<HistoryEntry<T>>, bool) {
+        let limit = limit.min(MAX_PAGE_SIZE);
+        let end = match before {
+            Some(before) => self.entries.partition_point(|entry| entry.index < before),
+            None => self.entries.len(),
+        };
+        let start = end.saturating_sub(limit);
+
+        (self.entries.range(start..end).cloned().collect(), start > 0)
+    }
+
+    /// Every kept entry, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &HistoryEntry<T>> {
+        self.entries.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn indices(entries: &[HistoryEntry<&str>]) -> Vec<u64> {
+        entries.iter().map(|entry| entry.index).collect()
+    }
+
+    #[test]
+    fn push_should_drop_oldest_entries_over_capacity() {
+        let mut history = History::new(3);
+
+        for item in ["a", "b", "c", "d"] {
+            history.push(item);
+        }
+        let (entries, has_more) = history.page(None, 10);
+
+        assert_eq!(indices(&entries), vec![1, 2, 3]);
+        assert_eq!(entries[0].item, "b");
+        assert!(!has_more);
+    }
+
+    #[test]
+    fn page_should_return_entries_preceding_cursor() {
+        let mut history = History::new(10);
+
+        for item in ["a", "b", "c", "d", "e"] {
+            history.push(item);
+        }
+        let (entries, has_more) = history.page(None, 2);
+
+        assert_eq!(indices(&entries), vec![3, 4]);
+        assert!(has_more);
+
+        let (entries, has_more) = history.page(Some(3), 2);
+
+        assert_eq!(indices(&entries), vec![1, 2]);
+        assert!(has_more);
+
+        let (entries, has_more) = history.page(Some(1), 2);
+
+        assert_eq!(indices(&entries), vec![0]);
+        assert!(!has_more);
+    }
+
+    #[test]
+    fn page_should_be_capped_at_max_page_size() {
+        let mut history = History::new(MAX_PAGE_SIZE * 2);
+
+        for _ in 0..MAX_PAGE_SIZE * 2 {
+            history.push("a");
+        }
+        let (entries, has_more) = history.page(None, usize::MAX);
+
+        assert_eq!(entries.len(), MAX_PAGE_SIZE);
+        assert!(has_more);
+    }
+}
@@ -0,0 +1,367 @@
+use crate::{
+    actors::connection_actor::{ConnectionActor, ConnectionMessage},
+    config::ServerConfig,
+    messages::{
+        outbound::{BacklogItem, ChatMessage, EventData, HistoryEntry, MemberInfo, MemberProgress},
+        profile::Profile,
+        state::{ReadingProgress, ReadingState},
+    },
+};
+use ractor::ActorRef;
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    time::Instant,
+};
+
+mod history;
+mod password;
+mod poll;
+mod readiness;
+mod role;
+
+pub use history::History;
+pub use password::{hash_password_blocking, verify_password_blocking};
+pub use poll::Poll;
+pub use readiness::{BarrierOutcome, ReadinessBarrier};
+pub use role::{Permission, Role};
+
+#[derive(Debug, Clone)]
+pub struct RoomMember {
+    pub role: Role,
+    /// `None` while the member's session is dangling.
+    pub connection_actor: Option<ActorRef<ConnectionActor>>,
+    pub joined_at: Instant,
+    pub progress: Option<ReadingProgress>,
+    /// Address the member joined from, remembered so that bans can cover it.
+    pub address: Option<IpAddr>,
+    pub profile: Option<Profile>,
+    /// Sequence number of the last event sent to the member. Each member's
+    /// events are numbered on their own, so that they follow without gaps.
+    pub last_seq: u64,
+}
+
+/// A broadcast event, along with the members it was meant for and the sequence
+/// number it got for each of them, so that only they get it replayed.
+#[derive(Debug, Clone)]
+pub struct LoggedEvent {
+    pub recipients: HashMap<String, u64>,
+    pub event: EventData,
+}
+
+#[derive(Debug, Clone)]
+pub struct Room {
+    pub room_id: String,
+    pub host_session_id: String,
+    /// Secret that lets people join the room as spectators.
+    pub spectator_token: String,
+    /// How many readers the room holds at most, dangling ones included.
+    pub max_members: usize,
+    /// Salted hash of the password clients need to join, if the room has one.
+    pub password_hash: Option<String>,
+    pub members: HashMap<String, RoomMember>,
+    /// Sessions and addresses that can't join the room again.
+    pub banned_sessions: HashSet<String>,
+    pub banned_addresses: HashSet<IpAddr>,
+    pub state_string: String,
+    pub state: Option<ReadingState>,
+    pub backlog: History<BacklogItem>,
+    pub chat: History<ChatMessage>,
+    /// Recent events of every member.
+    pub events: History<LoggedEvent>,
+    pub poll: Option<Poll>,
+    pub readiness: ReadinessBarrier,
+    /// Whether members with a dangling session are left out of the barrier.
+    pub readiness_ignores_dangling: bool,
+}
+
+impl Room {
+    pub fn new(
+        room_id: String,
+        host_session_id: String,
+        spectator_token: String,
+        max_members: usize,
+        password_hash: Option<String>,
+        config: &ServerConfig,
+    ) -> Self {
+        Room {
+            room_id,
+            host_session_id,
+            spectator_token,
+            max_members,
+            password_hash,
+            members: HashMap::new(),
+            banned_sessions: HashSet::new(),
+            banned_addresses: HashSet::new(),
+            state_string: "None".into(),
+            state: None,
+            backlog: History::new(config.backlog_size),
+            chat: History::new(config.chat_history_size),
+            events: History::new(config.event_replay_size),
+            poll: None,
+            readiness: ReadinessBarrier::Disabled,
+            readiness_ignores_dangling: false,
+        }
+    }
+
+    pub fn add_member(
+        &mut self,
+        session_id: String,
+        role: Role,
+        connection_actor: ActorRef<ConnectionActor>,
+        address: Option<IpAddr>,
+        profile: Option<Profile>,
+    ) {
+        self.members.insert(
+            session_id,
+            RoomMember {
+                role,
+                connection_actor: Some(connection_actor),
+                joined_at: Instant::now(),
+                progress: None,
+                address,
+                profile,
+                // numbering from 1 lets members that haven't seen any event yet
+                // reconnect with a `last_seen_seq` of 0
+                last_seq: 0,
+            },
+        );
+    }
+
+    pub fn remove_member(&mut self, session_id: &str) -> Option<RoomMember> {
+        self.members.remove(session_id)
+    }
+
+    /// Whether a member other than the given session already goes by the
+    /// profile's display name.
+    pub fn is_display_name_taken(&self, profile: &Profile, session_id: Option<&str>) -> bool {
+        self.members.iter().any(|(member_session_id, member)| {
+            Some(member_session_id.as_str()) != session_id
+                && member
+                    .profile
+                    .as_ref()
+                    .is_some_and(|member_profile| member_profile.has_same_display_name(profile))
+        })
+    }
+
+    /// Keeps the member's session, and address if known, from joining the room
+    /// again.
+    pub fn ban(&mut self, session_id: &str, address: Option<IpAddr>) {
+        self.banned_sessions.insert(session_id.into());
+
+        if let Some(address) = address {
+            self.banned_addresses.insert(address);
+        }
+    }
+
+    pub fn is_session_banned(&self, session_id: &str) -> bool {
+        self.banned_sessions.contains(session_id)
+    }
+
+    /// Always `false` for unknown addresses, which is every address when the
+    /// websocket layer doesn't report them.
+    pub fn is_banned(&self, address: Option<IpAddr>) -> bool {
+        address.is_some_and(|address| self.banned_addresses.contains(&address))
+    }
+
+    /// Rebinds the member to a new connection actor, or marks it as dangling
+    /// when `None` is passed. Returns `false` if no such member exists.
+    pub fn set_member_connection(
+        &mut self,
+        session_id: &str,
+        connection_actor: Option<ActorRef<ConnectionActor>>,
+    ) -> bool {
+        match self.members.get_mut(session_id) {
+            Some(member) => {
+                member.connection_actor = connection_actor;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Lists the members in the order they joined the room.
+    pub fn member_infos(&self) -> Vec<MemberInfo> {
+        let mut members = self.members.iter().collect::<Vec<_>>();
+        members.sort_by_key(|(_, member)| member.joined_at);
+
+        members
+            .into_iter()
+            .map(|(session_id, member)| MemberInfo {
+                session_id: session_id.clone(),
+                role: member.role,
+                profile: member.profile.clone(),
+                connected: member.connection_actor.is_some(),
+            })
+            .collect()
+    }
+
+    /// Reports how far along each client is compared to the room's state, in
+    /// the order they joined the room.
+    pub fn progress_spread(&self) -> Vec<MemberProgress> {
+        let mut clients = self
+            .members
+            .iter()
+            .filter(|(_, member)| member.role == Role::Client)
+            .collect::<Vec<_>>();
+        clients.sort_by_key(|(_, member)| member.joined_at);
+
+        clients
+            .into_iter()
+            .map(|(session_id, member)| MemberProgress {
+                session_id: session_id.clone(),
+                progress: member.progress.clone(),
+                lines_behind: match (&self.state, &member.progress) {
+                    (Some(state), Some(progress)) => {
+                        Some(state.line_index.saturating_sub(progress.line_index))
+                    }
+                    _ => None,
+                },
+            })
+            .collect()
+    }
+
+    /// Pushes the event to every member that currently has a live connection.
+    pub fn broadcast(&mut self, event: EventData) {
+        self.broadcast_where(|_, _| true, event);
+    }
+
+    /// Same as [`Room::broadcast`], but skips the member the event is about.
+    pub fn broadcast_except(&mut self, excluded_session_id: &str, event: EventData) {
+        self.broadcast_where(|session_id, _| session_id != excluded_session_id, event);
+    }
+
+    /// Pushes the event to every connected member matching the predicate.
+    /// Dangling members matching it get the event replayed if they reconnect.
+    pub fn broadcast_where(
+        &mut self,
+        predicate: impl Fn(&str, &RoomMember) -> bool,
+        event: EventData,
+    ) {
+        let mut recipients = HashMap::new();
+
+        for (session_id, member) in &mut self.members {
+            if !predicate(session_id, member) {
+                continue;
+            }
+
+            member.last_seq += 1;
+            recipients.insert(session_id.clone(), member.last_seq);
+
+            if let Some(connection_actor) = &member.connection_actor {
+                // the connection actor might be stopping at the same time, in
+                // which case it doesn't need the event anymore
+                let _ = connection_actor.send_message(ConnectionMessage::PushEvent {
+                    seq: member.last_seq,
+                    event: event.clone(),
+                });
+            }
+        }
+
+        self.events.push(LoggedEvent { recipients, event });
+    }
+
+    /// The events the member was sent after `last_seen_seq`, indexed by their
+    /// sequence number, or `None` if some of them are no longer kept.
+    pub fn missed_events(
+        &self,
+        session_id: &str,
+        last_seen_seq: u64,
+    ) -> Option<Vec<HistoryEntry<EventData>>> {
+        let last_seq = self.members.get(session_id)?.last_seq;
+
+        if last_seen_seq > last_seq {
+            return None;
+        }
+
+        let missed: Vec<_> = self
+            .events
+            .iter()
+            .filter_map(|entry| {
+                let seq = *entry.item.recipients.get(session_id)?;
+
+                (seq > last_seen_seq).then(|| HistoryEntry {
+                    index: seq,
+                    item: entry.item.event.clone(),
+                })
+            })
+            .collect();
+
+        // the member's events are numbered without gaps, so any missing one
+        // was dropped from the log
+        (missed.len() as u64 == last_seq - last_seen_seq).then_some(missed)
+    }
+
+    /// Moves the host role to the given member, demoting the previous host to a
+    /// client if it's still in the room.
+    pub fn set_host(&mut self, session_id: &str) {
+        if let Some(previous_host) = self.members.get_mut(&self.host_session_id) {
+            previous_host.role = Role::Client;
+        }
+
+        if let Some(new_host) = self.members.get_mut(session_id) {
+            new_host.role = Role::Host;
+        }
+
+        self.host_session_id = session_id.into();
+    }
+
+    /// Picks the client that joined the earliest, preferring connected clients
+    /// over dangling ones.
+    pub fn longest_connected_client(&self) -> Option<String> {
+        self.members
+            .iter()
+            .filter(|(_, member)| member.role == Role::Client)
+            .min_by_key(|(_, member)| (member.connection_actor.is_none(), member.joined_at))
+            .map(|(session_id, _)| session_id.clone())
+    }
+
+    pub fn set_state(&mut self, state: ReadingState) {
+        self.backlog.push(BacklogItem::State {
+            state: state.clone(),
+        });
+        self.state = Some(state);
+
+        if self.readiness.is_enabled() {
+            self.readiness.start_round();
+        }
+    }
+
+    /// Checks the readiness barrier against the clients currently in the room.
+    pub fn evaluate_readiness(&mut self) -> Option<BarrierOutcome> {
+        let ignore_dangling = self.readiness_ignores_dangling;
+        let required = self
+            .members
+            .iter()
+            .filter(|(_, member)| member.role == Role::Client)
+            .filter(|(_, member)| !ignore_dangling || member.connection_actor.is_some())
+            .map(|(session_id, _)| session_id.as_str());
+
+        self.readiness.evaluate(required)
+    }
+
+    pub fn add_chat_message(&mut self, message: ChatMessage) -> HistoryEntry<ChatMessage> {
+        let index = self.chat.push(message.clone());
+
+        HistoryEntry {
+            index,
+            item: message,
+        }
+    }
+
+    /// Whether another reader can join. Dangling sessions keep their slot so
+    /// that they can always come back, while spectators never take one.
+    pub fn is_full(&self) -> bool {
+        let readers = self
+            .members
+            .values()
+            .filter(|member| member.role != Role::Spectator)
+            .count();
+
+        readers >= self.max_members
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+}
@@ -0,0 +1,70 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use tokio::{sync::Semaphore, task::JoinError};
+
+/// Each Argon2 run takes a blocking thread and a good deal of memory, so only
+/// this many run at once however many passwords clients send.
+const MAX_CONCURRENT_HASHES: usize = 4;
+
+static HASH_PERMITS: Semaphore = Semaphore::const_new(MAX_CONCURRENT_HASHES);
+
+/// Hashes the room password with a random salt, returning it in the PHC string
+/// format so that the salt and parameters travel along with the hash.
+pub fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("hashing with the default parameters should not fail")
+        .to_string()
+}
+
+pub fn verify_password(password_hash: &str, password: &str) -> bool {
+    let Ok(password_hash) = PasswordHash::new(password_hash) else {
+        return false;
+    };
+
+    Argon2::default()
+        .verify_password(password.as_bytes(), &password_hash)
+        .is_ok()
+}
+
+/// Runs `hash_password` on the blocking pool once a hashing slot frees up.
+pub async fn hash_password_blocking(password: String) -> Result<String, JoinError> {
+    let _permit = HASH_PERMITS
+        .acquire()
+        .await
+        .expect("the semaphore should never be closed");
+
+    tokio::task::spawn_blocking(move || hash_password(&password)).await
+}
+
+/// Runs `verify_password` on the blocking pool once a hashing slot frees up.
+pub async fn verify_password_blocking(
+    password_hash: String,
+    password: String,
+) -> Result<bool, JoinError> {
+    let _permit = HASH_PERMITS
+        .acquire()
+        .await
+        .expect("the semaphore should never be closed");
+
+    tokio::task::spawn_blocking(move || verify_password(&password_hash, &password)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_should_be_salted_and_verifiable() {
+        let first_hash = hash_password("el psy kongroo");
+        let second_hash = hash_password("el psy kongroo");
+
+        assert_ne!(first_hash, second_hash);
+        assert!(verify_password(&first_hash, "el psy kongroo"));
+        assert!(!verify_password(&first_hash, "tuturu"));
+    }
+}
@@ -0,0 +1,127 @@
+use crate::messages::outbound::BacklogItem;
+use std::collections::HashMap;
+
+const MIN_OPTIONS: usize = 2;
+const MAX_OPTIONS: usize = 10;
+const MAX_OPTION_LENGTH: usize = 200;
+/// Longest a poll can stay open on its own, a day.
+const MAX_DURATION_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// A vote on a choice in the visual novel. Every member gets a single vote,
+/// which can be changed for as long as the poll is open.
+#[derive(Debug, Clone)]
+pub struct Poll {
+    pub poll_id: String,
+    pub options: Vec<String>,
+    pub votes: HashMap<String, usize>,
+}
+
+impl Poll {
+    pub fn validate_options(options: &[String]) -> Result<(), String> {
+        if !(MIN_OPTIONS..=MAX_OPTIONS).contains(&options.len()) {
+            return Err(format!(
+                "a poll must have between {} and {} options",
+                MIN_OPTIONS, MAX_OPTIONS
+            ));
+        }
+
+        let has_invalid_option = options
+            .iter()
+            .any(|option| option.trim().is_empty() || option.chars().count() > MAX_OPTION_LENGTH);
+
+        if has_invalid_option {
+            return Err(format!(
+                "poll options must be between 1 and {} characters long",
+                MAX_OPTION_LENGTH
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn validate_duration(duration_ms: Option<u64>) -> Result<(), String> {
+        match duration_ms {
+            Some(duration_ms) if duration_ms > MAX_DURATION_MS => {
+                Err(format!("duration_ms must be at most {}", MAX_DURATION_MS))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn tallies(&self) -> Vec<u32> {
+        let mut tallies = vec![0; self.options.len()];
+
+        for option_index in self.votes.values() {
+            tallies[*option_index] += 1;
+        }
+
+        tallies
+    }
+
+    /// The option with the most votes, or `None` if nobody voted or the top
+    /// options are tied.
+    pub fn winner(&self) -> Option<usize> {
+        let tallies = self.tallies();
+        let max = *tallies.iter().max()?;
+
+        if max == 0 || tallies.iter().filter(|tally| **tally == max).count() > 1 {
+            return None;
+        }
+
+        tallies.iter().position(|tally| *tally == max)
+    }
+
+    pub fn result(&self) -> BacklogItem {
+        BacklogItem::PollResult {
+            poll_id: self.poll_id.clone(),
+            options: self.options.clone(),
+            tallies: self.tallies(),
+            winner: self.winner(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn poll(votes: &[usize]) -> Poll {
+        Poll {
+            poll_id: "poll".into(),
+            options: vec!["a".into(), "b".into(), "c".into()],
+            votes: votes
+                .iter()
+                .enumerate()
+                .map(|(voter, option_index)| (voter.to_string(), *option_index))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn winner_should_be_option_with_most_votes() {
+        let poll = poll(&[1, 2, 1]);
+
+        assert_eq!(poll.tallies(), vec![0, 2, 1]);
+        assert_eq!(poll.winner(), Some(1));
+    }
+
+    #[test]
+    fn winner_should_be_none_on_tie_or_without_votes() {
+        assert_eq!(poll(&[0, 1]).winner(), None);
+        assert_eq!(poll(&[]).winner(), None);
+    }
+
+    #[test]
+    fn validate_options_should_reject_too_few_options() {
+        assert!(Poll::validate_options(&["a".into()]).is_err());
+        assert!(Poll::validate_options(&["a".into(), " ".into()]).is_err());
+        assert!(Poll::validate_options(&["a".into(), "b".into()]).is_ok());
+    }
+
+    #[test]
+    fn validate_duration_should_reject_durations_over_a_day() {
+        assert!(Poll::validate_duration(None).is_ok());
+        assert!(Poll::validate_duration(Some(60_000)).is_ok());
+        assert!(Poll::validate_duration(Some(u64::MAX)).is_err());
+    }
+}
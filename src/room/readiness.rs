@@ -0,0 +1,164 @@
+use crate::messages::state::ReadingState;
+use std::collections::HashSet;
+
+/// Room-level state machine of the "wait for everyone" mode. While enabled,
+/// every state change starts a new round in which the readers mark themselves
+/// as ready on the current line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadinessBarrier {
+    Disabled,
+    Waiting {
+        ready: HashSet<String>,
+        /// State to advance to automatically once everyone is ready.
+        queued_state: Option<ReadingState>,
+    },
+    AllReady,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum BarrierOutcome {
+    /// Everyone is ready and there's nothing queued, so the host should be told.
+    AllReady,
+    /// Everyone is ready and the queued state should be applied.
+    Advance(ReadingState),
+}
+
+impl ReadinessBarrier {
+    pub fn is_enabled(&self) -> bool {
+        !matches!(self, ReadinessBarrier::Disabled)
+    }
+
+    pub fn enable(&mut self) {
+        if !self.is_enabled() {
+            self.start_round();
+        }
+    }
+
+    /// Returns the queued state, which should be applied right away since
+    /// nothing is holding it back anymore.
+    pub fn disable(&mut self) -> Option<ReadingState> {
+        match std::mem::replace(self, ReadinessBarrier::Disabled) {
+            ReadinessBarrier::Waiting { queued_state, .. } => queued_state,
+            _ => None,
+        }
+    }
+
+    /// Called whenever the room moves on to a new line.
+    pub fn start_round(&mut self) {
+        *self = ReadinessBarrier::Waiting {
+            ready: HashSet::new(),
+            queued_state: None,
+        };
+    }
+
+    /// Returns `false` if the barrier is disabled.
+    pub fn set_ready(&mut self, session_id: &str, is_ready: bool) -> bool {
+        match self {
+            ReadinessBarrier::Disabled => false,
+            ReadinessBarrier::Waiting { ready, .. } => {
+                if is_ready {
+                    ready.insert(session_id.into());
+                } else {
+                    ready.remove(session_id);
+                }
+                true
+            }
+            ReadinessBarrier::AllReady => true,
+        }
+    }
+
+    /// Holds the state back until everyone is ready. Returns it instead if it
+    /// can be applied right away.
+    pub fn queue(&mut self, state: ReadingState) -> Option<ReadingState> {
+        match self {
+            ReadinessBarrier::Waiting { queued_state, .. } => {
+                *queued_state = Some(state);
+                None
+            }
+            ReadinessBarrier::Disabled | ReadinessBarrier::AllReady => Some(state),
+        }
+    }
+
+    /// Checks whether every required reader is ready, moving on to the next
+    /// phase if so.
+    pub fn evaluate<'a>(
+        &mut self,
+        mut required: impl Iterator<Item = &'a str>,
+    ) -> Option<BarrierOutcome> {
+        let ReadinessBarrier::Waiting {
+            ready,
+            queued_state,
+        } = self
+        else {
+            return None;
+        };
+
+        if !required.all(|session_id| ready.contains(session_id)) {
+            return None;
+        }
+
+        match queued_state.take() {
+            Some(state) => Some(BarrierOutcome::Advance(state)),
+            None => {
+                *self = ReadinessBarrier::AllReady;
+                Some(BarrierOutcome::AllReady)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading_state() -> ReadingState {
+        ReadingState {
+            game_id: "game".into(),
+            scene_id: "scene".into(),
+            line_index: 1,
+            line_text: "".into(),
+            speaker: None,
+            content_hash: "ff".into(),
+        }
+    }
+
+    #[test]
+    fn evaluate_should_report_all_ready_once_everyone_is_ready() {
+        let mut barrier = ReadinessBarrier::Disabled;
+        barrier.enable();
+
+        barrier.set_ready("a", true);
+        assert_eq!(barrier.evaluate(["a", "b"].into_iter()), None);
+
+        barrier.set_ready("b", true);
+        assert_eq!(
+            barrier.evaluate(["a", "b"].into_iter()),
+            Some(BarrierOutcome::AllReady)
+        );
+        assert_eq!(barrier, ReadinessBarrier::AllReady);
+        assert_eq!(barrier.evaluate(["a", "b"].into_iter()), None);
+    }
+
+    #[test]
+    fn evaluate_should_advance_to_queued_state() {
+        let mut barrier = ReadinessBarrier::Disabled;
+        barrier.enable();
+
+        assert_eq!(barrier.queue(reading_state()), None);
+        barrier.set_ready("a", true);
+
+        assert_eq!(
+            barrier.evaluate(["a"].into_iter()),
+            Some(BarrierOutcome::Advance(reading_state()))
+        );
+    }
+
+    #[test]
+    fn disabled_barrier_should_not_hold_states_back() {
+        let mut barrier = ReadinessBarrier::Disabled;
+
+        assert_eq!(barrier.queue(reading_state()), Some(reading_state()));
+        assert!(!barrier.set_ready("a", true));
+        assert_eq!(barrier.evaluate(["a"].into_iter()), None);
+    }
+}
@@ -0,0 +1,125 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Host,
+    Client,
+    /// Can follow the room, but not take part in it.
+    Spectator,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    WriteState,
+    TransferHost,
+    Chat,
+    ManagePoll,
+    Vote,
+    ManageReadiness,
+    MarkReady,
+    ReportProgress,
+    ManageInvites,
+    Moderate,
+}
+
+impl Role {
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        match (self, permission) {
+            (Role::Host, _) => true,
+            (
+                Role::Client,
+                Permission::WriteState
+                | Permission::TransferHost
+                | Permission::ManagePoll
+                | Permission::ManageReadiness
+                | Permission::ManageInvites
+                | Permission::Moderate,
+            ) => false,
+            (
+                Role::Client,
+                Permission::Chat
+                | Permission::Vote
+                | Permission::MarkReady
+                | Permission::ReportProgress,
+            ) => true,
+            (Role::Spectator, _) => false,
+        }
+    }
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Role::Host => "host",
+            Role::Client => "client",
+            Role::Spectator => "spectator",
+        })
+    }
+}
+
+impl Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Permission::WriteState => "write_state",
+            Permission::TransferHost => "transfer_host",
+            Permission::Chat => "chat",
+            Permission::ManagePoll => "manage_poll",
+            Permission::Vote => "vote",
+            Permission::ManageReadiness => "manage_readiness",
+            Permission::MarkReady => "mark_ready",
+            Permission::ReportProgress => "report_progress",
+            Permission::ManageInvites => "manage_invites",
+            Permission::Moderate => "moderate",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_host_should_be_able_to_write_state() {
+        assert!(Role::Host.has_permission(Permission::WriteState));
+        assert!(!Role::Client.has_permission(Permission::WriteState));
+    }
+
+    #[test]
+    fn host_and_client_should_be_able_to_chat() {
+        assert!(Role::Host.has_permission(Permission::Chat));
+        assert!(Role::Client.has_permission(Permission::Chat));
+    }
+
+    #[test]
+    fn only_host_should_be_able_to_manage_polls() {
+        assert!(Role::Host.has_permission(Permission::ManagePoll));
+        assert!(!Role::Client.has_permission(Permission::ManagePoll));
+        assert!(Role::Client.has_permission(Permission::Vote));
+    }
+
+    #[test]
+    fn only_host_should_be_able_to_transfer_host() {
+        assert!(Role::Host.has_permission(Permission::TransferHost));
+        assert!(!Role::Client.has_permission(Permission::TransferHost));
+    }
+
+    #[test]
+    fn spectator_should_not_have_any_permission() {
+        for permission in [
+            Permission::WriteState,
+            Permission::TransferHost,
+            Permission::Chat,
+            Permission::ManagePoll,
+            Permission::Vote,
+            Permission::ManageReadiness,
+            Permission::MarkReady,
+            Permission::ReportProgress,
+            Permission::ManageInvites,
+            Permission::Moderate,
+        ] {
+            assert!(!Role::Spectator.has_permission(permission));
+        }
+    }
+}
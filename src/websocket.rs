@@ -0,0 +1,212 @@
+//! A small websocket server in the spirit of simple-websockets, which it
+//! replaces because it drops the peer address when accepting connections.
+
+use futures_util::{SinkExt, StreamExt};
+use std::{io, net::SocketAddr};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+};
+use tokio_tungstenite::{accept_async, tungstenite};
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl Message {
+    fn into_tungstenite(self) -> tungstenite::Message {
+        match self {
+            Message::Text(text) => tungstenite::Message::Text(text),
+            Message::Binary(bytes) => tungstenite::Message::Binary(bytes),
+        }
+    }
+
+    /// Control frames are answered by tungstenite itself and yield `None`.
+    fn from_tungstenite(message: tungstenite::Message) -> Option<Self> {
+        match message {
+            tungstenite::Message::Text(text) => Some(Message::Text(text)),
+            tungstenite::Message::Binary(bytes) => Some(Message::Binary(bytes)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum ResponderCommand {
+    Message(Message),
+    Close,
+}
+
+/// Sends frames to a connected client. The connection is closed once every
+/// clone of its responder is dropped.
+#[derive(Debug, Clone)]
+pub struct Responder {
+    tx: UnboundedSender<ResponderCommand>,
+    client_id: u64,
+    address: SocketAddr,
+}
+
+impl Responder {
+    /// Returns whether the client is still connected.
+    pub fn send(&self, message: Message) -> bool {
+        self.tx.send(ResponderCommand::Message(message)).is_ok()
+    }
+
+    pub fn close(&self) {
+        let _ = self.tx.send(ResponderCommand::Close);
+    }
+
+    pub fn client_id(&self) -> u64 {
+        self.client_id
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+}
+
+#[derive(Debug)]
+pub enum Event {
+    Connect(u64, Responder),
+    Disconnect(u64),
+    Message(u64, Message),
+}
+
+#[derive(Debug)]
+pub struct EventHub {
+    rx: UnboundedReceiver<Event>,
+}
+
+impl EventHub {
+    pub async fn poll_async(&mut self) -> Event {
+        self.rx
+            .recv()
+            .await
+            .expect("the listener should outlive the event hub")
+    }
+}
+
+/// Starts accepting websocket connections on every interface.
+pub async fn launch(port: u16) -> io::Result<EventHub> {
+    Ok(launch_from_listener(
+        TcpListener::bind(("0.0.0.0", port)).await?,
+    ))
+}
+
+pub fn launch_from_listener(listener: TcpListener) -> EventHub {
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        let mut next_client_id: u64 = 0;
+
+        loop {
+            // failing to accept one connection shouldn't stop the listener
+            let Ok((stream, address)) = listener.accept().await else {
+                continue;
+            };
+
+            tokio::spawn(handle_connection(
+                stream,
+                address,
+                next_client_id,
+                tx.clone(),
+            ));
+            next_client_id = next_client_id.wrapping_add(1);
+        }
+    });
+
+    EventHub { rx }
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    address: SocketAddr,
+    client_id: u64,
+    event_tx: UnboundedSender<Event>,
+) {
+    let Ok(ws_stream) = accept_async(stream).await else {
+        return;
+    };
+
+    let (mut outgoing, mut incoming) = ws_stream.split();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    let responder = Responder {
+        tx,
+        client_id,
+        address,
+    };
+
+    if event_tx.send(Event::Connect(client_id, responder)).is_err() {
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            command = rx.recv() => match command {
+                Some(ResponderCommand::Message(message)) => {
+                    if outgoing.send(message.into_tungstenite()).await.is_err() {
+                        break;
+                    }
+                }
+                // closed on purpose, or every responder was dropped
+                Some(ResponderCommand::Close) | None => break,
+            },
+            message = incoming.next() => match message {
+                Some(Ok(message)) => {
+                    if let Some(message) = Message::from_tungstenite(message) {
+                        let _ = event_tx.send(Event::Message(client_id, message));
+                    }
+                }
+                Some(Err(_)) | None => break,
+            },
+        }
+    }
+
+    let _ = outgoing.close().await;
+    let _ = event_tx.send(Event::Disconnect(client_id));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+    use tokio_tungstenite::connect_async;
+
+    #[tokio::test]
+    async fn connections_should_keep_their_peer_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut event_hub = launch_from_listener(listener);
+
+        let (mut client, _) = connect_async(format!("ws://127.0.0.1:{}", port))
+            .await
+            .unwrap();
+        let Event::Connect(client_id, responder) = event_hub.poll_async().await else {
+            panic!("expected the client to connect first");
+        };
+        assert_eq!(responder.address().ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+
+        client
+            .send(tungstenite::Message::Text("ping".into()))
+            .await
+            .unwrap();
+        let Event::Message(message_client_id, Message::Text(text)) = event_hub.poll_async().await
+        else {
+            panic!("expected a text message");
+        };
+        assert_eq!((message_client_id, text.as_str()), (client_id, "ping"));
+
+        responder.send(Message::Text("pong".into()));
+        let reply = client.next().await.unwrap().unwrap();
+        assert_eq!(reply, tungstenite::Message::Text("pong".into()));
+
+        responder.close();
+        let Event::Disconnect(disconnected_client_id) = event_hub.poll_async().await else {
+            panic!("expected the client to disconnect");
+        };
+        assert_eq!(disconnected_client_id, client_id);
+    }
+}